@@ -1,31 +1,115 @@
-use minifb::{MouseMode, Window, WindowOptions, ScaleMode, Scale};
-extern crate cairo;
-use cairo::{ ImageSurface, Format, Context };
-use font_kit::family_name::FamilyName;
-use font_kit::properties::Properties;
-use font_kit::source::SystemSource;
-use image::io::Reader as ImageReader;
-use image::imageops::FilterType;
-use std::error::Error;
-use std::fs::File;
-use std::collections::HashMap;
 use std::cmp;
+use std::fmt;
+use std::str::FromStr;
 use std::ops::{Not};
 
-#[derive(Clone, Copy, Debug)]
-pub struct ScreenPosition(
-    pub f64,
-    pub f64
-);
+pub mod notation;
+pub mod pgn;
+mod zobrist;
+mod bitboard;
+mod perft;
+mod error;
+mod analysis;
+pub mod engine;
+pub mod polyglot;
+pub mod clock;
+pub mod save;
+pub mod variant;
+#[cfg(feature = "gui")] mod board;
+#[cfg(feature = "gui")] pub mod assets;
+#[cfg(feature = "gui")] mod labels;
+#[cfg(feature = "gui")] pub mod window;
+
+#[cfg(feature = "gui")]
+pub use board::{Board, Drawable, Gesture, Overlay, Overlays, PngDrawable, ScreenPosition, SIDE_PANEL_WIDTH};
+pub use error::ChessError;
+pub use analysis::Pin;
+pub use variant::Variant;
+
+use bitboard::{Bitboard, square_bit, square_index};
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-pub struct BoardPosition(
+// File from 0 (a) to 7 (h), then row from 0 (rank 8) to 7 (rank 1).
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Square(
     pub usize,
     pub usize
 );
 
-pub trait Drawable {
-    fn draw(&self, dt: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {Ok(())}
+// Why a string is not a square name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SquareParseError {
+    name: String
+}
+impl fmt::Display for SquareParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid square '{}'", self.name)
+    }
+}
+impl std::error::Error for SquareParseError {}
+
+// Algebraic names, e.g. Square(4, 6) is "e2".
+impl fmt::Display for Square {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", (b'a' + self.0 as u8) as char, 8 - self.1)
+    }
+}
+
+impl Square {
+    // Whether both coordinates are within 0..8, e.g. for a square that came off the network.
+    pub fn is_on_board(&self) -> bool {
+        self.0 < 8 && self.1 < 8
+    }
+}
+
+impl FromStr for Square {
+    type Err = SquareParseError;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let bytes = name.as_bytes();
+        if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+            return Err(SquareParseError { name: name.to_string() });
+        }
+        Ok(Square((bytes[0] - b'a') as usize, (b'8' - bytes[1]) as usize))
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum CastleSide {
+    // Towards the h-file
+    KingSide,
+    // Towards the a-file
+    QueenSide
+}
+
+// A move as generated for a position, knowing what kind of move it is. Get one from legal_moves,
+// find_move or the notation parsers rather than building it by hand.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    // What a pawn reaching the last rank turns into
+    pub promotion: Option<Piece>,
+    // Takes a piece, en passant included
+    pub capture: bool,
+    pub castle: Option<CastleSide>,
+    pub en_passant: bool,
+}
+
+impl Move {
+    // Where the captured piece stands, which differs from to only for en passant.
+    fn captured_square(&self) -> Square {
+        if self.en_passant {Square(self.to.0, self.from.1)} else {self.to}
+    }
+}
+
+// UCI long algebraic notation, e.g. "e2e4" or "e7e8q".
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.from, self.to)?;
+        if let Some(piece) = self.promotion {
+            write!(f, "{}", sq_to_char(BoardSquare::Occupied(piece, Color::Black)))?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
@@ -45,7 +129,7 @@ impl Not for Color {
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
-enum Piece {
+pub enum Piece {
     Pawn(bool),
     Knight,
     Bishop,
@@ -54,476 +138,993 @@ enum Piece {
     King
 }
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
-enum BoardSquare {
-    Empty,
-    Occupied(Piece, Color)
-}
-
-struct EmptyDrawable {}
-impl Drawable for EmptyDrawable {}
+// Pieces a pawn may promote to, in the order they are offered by the promotion picker.
+pub const PROMOTION_PIECES: [Piece; 4] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight];
 
-pub struct PngDrawable {
-	//pngImage: Source<'a>,
-    surface: ImageSurface,
-    data: Vec<u32>,
-    width_ratio: f64, 
-    height_ratio: f64,
+// How a finished game ended.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum GameOutcome {
+    Checkmate { winner: Color },
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    // The loser's flag fell
+    Timeout { winner: Color },
+    // King of the Hill: the winner's king reached the centre
+    KingOfTheHill { winner: Color },
+    // Three-check: the winner gave its third check
+    ThreeChecks { winner: Color },
+    // Atomic: the loser's king was blown up
+    KingExploded { winner: Color },
+    // Antichess: the winner has no legal move, most likely having lost every piece
+    NoMovesLeft { winner: Color },
+    // A flag fell, but the other side had only a king and could never have mated
+    TimeoutVsInsufficientMaterial,
 }
 
-impl PngDrawable {
-    pub fn new(path: &str, width: f64, height: f64) -> Result<Self, Box<dyn Error>> {
-        let mut openf = File::open(path)?;
-        let imsurf = ImageSurface::create_from_png(&mut openf)?;
-
-        let img_height = imsurf.height() as f64;
-        let img_width = imsurf.width() as f64;
-        let width_ratio = width / img_width;
-        let height_ratio = height / img_height;
-
-        Ok(PngDrawable {surface: imsurf, data: vec![], width_ratio, height_ratio})
+impl GameOutcome {
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            GameOutcome::Checkmate { winner } | GameOutcome::Timeout { winner } | GameOutcome::KingOfTheHill { winner }
+                | GameOutcome::ThreeChecks { winner } | GameOutcome::KingExploded { winner } | GameOutcome::NoMovesLeft { winner } => Some(*winner),
+            _ => None
+        }
     }
 }
 
-impl Drawable for PngDrawable {
-    fn draw(&self, ctx: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {
-        ctx.save()?;
-        ctx.translate(position.0, position.1);
-        ctx.scale(self.width_ratio, self.height_ratio);
-        ctx.set_source_surface(&self.surface, 0.0, 0.0)?;
-        ctx.paint()?;
-        ctx.restore()?;
-        Ok(())
+impl fmt::Display for GameOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameOutcome::Checkmate { winner } => write!(f, "{:?} wins by checkmate", winner),
+            GameOutcome::Stalemate => write!(f, "Draw by stalemate"),
+            GameOutcome::InsufficientMaterial => write!(f, "Draw by insufficient material"),
+            GameOutcome::FiftyMoveRule => write!(f, "Draw by the fifty-move rule"),
+            GameOutcome::ThreefoldRepetition => write!(f, "Draw by threefold repetition"),
+            GameOutcome::Timeout { winner } => write!(f, "{:?} wins on time", winner),
+            GameOutcome::TimeoutVsInsufficientMaterial => write!(f, "Draw by timeout vs insufficient material"),
+            GameOutcome::KingOfTheHill { winner } => write!(f, "{:?} wins with the king in the centre", winner),
+            GameOutcome::ThreeChecks { winner } => write!(f, "{:?} wins by giving three checks", winner),
+            GameOutcome::KingExploded { winner } => write!(f, "{:?} wins by blowing up the king", winner),
+            GameOutcome::NoMovesLeft { winner } => write!(f, "{:?} wins by running out of moves", winner),
+        }
     }
 }
 
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+enum BoardSquare {
+    Empty,
+    Occupied(Piece, Color)
+}
+
 #[derive(Clone, Debug)]
 pub struct GameState {
+    variant: Variant,
 	board_state: [[BoardSquare; 8]; 8],
+    // The same pieces as board_state, changed only through set_square so the two always agree.
+    bitboards: bitboard::Bitboards,
 	pub turn: Color,
     black_can_castle_left: bool,
     white_can_castle_left: bool,
     black_can_castle_right: bool,
     white_can_castle_right: bool,
+    // Files of the rooks the castling rights belong to, by color_index and then left and right. Always the
+    // corners outside Chess960.
+    castling_rooks: [[usize; 2]; 2],
     // If en passant is legal, the square of the pawn which can be captured via en passant.
-    en_passant_square: Option<BoardPosition>,
+    en_passant_square: Option<Square>,
+    // Half-moves since the last capture or pawn move, for the fifty-move rule.
+    pub halfmove_clock: usize,
+    // Starts at 1 and goes up after each of Black's moves.
+    pub fullmove_number: usize,
+    // By color_index, for Three-check
+    checks_given: [u8; 2],
+    // Zobrist hash of the current position, kept up to date move by move.
+    hash: u64,
+    // Hashes of every position reached so far, current one last, for repetition detection.
+    position_history: Vec<u64>,
+    // Everything needed to take back each move played so far, most recent last.
+    undo_stack: Vec<UndoInfo>,
+    // Moves that were taken back and can be replayed, next one last. Playing any other move forgets them.
+    redo_stack: Vec<Move>,
+}
+
+// What do_move changed that can't be worked out again from the move itself.
+#[derive(Clone, Debug)]
+struct UndoInfo {
+    mv: Move,
+    // The moving piece as it was, so a pawn gets its has_moved flag back
+    moved: BoardSquare,
+    captured: BoardSquare,
+    castling: (bool, bool, bool, bool),
+    en_passant_square: Option<Square>,
+    halfmove_clock: usize,
+    checks_given: [u8; 2],
+    // Atomic: the pieces a capture blew up, capturing piece included, where they stood
+    exploded: Vec<(Square, BoardSquare)>,
 }
+
 impl GameState {
     fn new() -> Self {
         GameState {
+            variant: Variant::Standard,
             board_state: [[BoardSquare::Empty; 8]; 8],
+            bitboards: bitboard::Bitboards::default(),
             turn: Color::White,
             black_can_castle_left: true,
             white_can_castle_left: true,
             black_can_castle_right: true,
             white_can_castle_right: true,
+            castling_rooks: [[0, 7]; 2],
             en_passant_square: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            checks_given: [0; 2],
+            hash: 0,
+            position_history: vec![],
+            undo_stack: vec![],
+            redo_stack: vec![],
         }
     }
-    fn piece_iterator<'a>(&'a self) -> Box<dyn Iterator<Item = (BoardPosition, BoardSquare)> + 'a> {
-        Box::new((0..64).map(|x| {
-            let pos = BoardPosition(x / 8, x % 8);
-            (pos, self.board_state[pos.1][pos.0])
-        }).filter(|&(_, state)| state != BoardSquare::Empty))
+    // Forget earlier positions and start counting from the current one, e.g. after setting up a new position.
+    fn reset_history(&mut self) {
+        self.halfmove_clock = 0;
+        self.hash = self.compute_hash();
+        self.position_history = vec![self.hash];
+        self.undo_stack.clear();
+        self.redo_stack.clear();
     }
-	pub fn is_legal(&self, from_pos: BoardPosition, to_pos: BoardPosition) -> bool {
-        if !self.is_legal_start(from_pos) {
-            return false;
-        }
-        // Check square is not occupied by friendly pieces
-        if self.is_legal_start(to_pos) {
-            return false;
+    // Put sq on pos, replacing whatever was there.
+    fn set_square(&mut self, pos: Square, sq: BoardSquare) {
+        self.bitboards.toggle(pos, self.board_state[pos.1][pos.0]);
+        self.board_state[pos.1][pos.0] = sq;
+        self.bitboards.toggle(pos, sq);
+    }
+    fn piece_iterator<'a>(&'a self) -> Box<dyn Iterator<Item = (Square, BoardSquare)> + 'a> {
+        Box::new(bitboard::squares(self.bitboards.occupied()).map(|pos| (pos, self.board_state[pos.1][pos.0])))
+    }
+	pub fn is_legal(&self, mv: Move) -> bool {
+        self.legal_moves_from(mv.from).contains(&mv)
+    }
+    // The legal move from from_pos to to_pos, if there is one. A promotion piece is needed exactly when a pawn reaches the last rank.
+    pub fn find_move(&self, from_pos: Square, to_pos: Square, promotion: Option<Piece>) -> Option<Move> {
+        self.legal_moves_from(from_pos).into_iter().find(|mv| mv.to == to_pos && mv.promotion == promotion)
+    }
+    // Every legal move for the side to move. A promoting pawn move appears once for each piece it can become.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        for from_pos in bitboard::squares(self.bitboards.color(self.turn)) {
+            moves.extend(self.legal_moves_from(from_pos));
         }
-        // Check basic legality of piece movement
-        if !self.is_attacking_movement_ok(from_pos, to_pos, false) {
-            let pos_diff = ((to_pos.0 as i64 - from_pos.0 as i64), (to_pos.1 as i64 - from_pos.1 as i64));
-            // Check special rules (non-attacking pawn move, en passant, castling)
-            let could_be_legal = match self.board_state[from_pos.1][from_pos.0] {
-                BoardSquare::Occupied(Piece::Pawn(has_moved), _) => {
-                    let pawn_dir = if self.turn == Color::White {-1} else {1};
-                    if let BoardSquare::Occupied(_, _) = self.board_state[to_pos.1][to_pos.0] {
-                        // Direct attack case already handled
-                        false
-                    } else {
-                        if pos_diff.0 == 0 {
-                            // (!has_moved && (0, 2) movement) || (0, 1) movement
-                            pos_diff.1 == pawn_dir || (pos_diff.1 == pawn_dir * 2 && !has_moved)
-                        } else {
-                            // En passant case
-                            if let Some(pass_pos) = self.en_passant_square {
-                                pos_diff.0.abs() == 1 && pos_diff.1 == pawn_dir && to_pos.0 == pass_pos.0 && to_pos.1 == (pass_pos.1 as i64 + pawn_dir) as usize
-                            } else {
-                                false
-                            }
-                        }
-                    }
-                },
-                BoardSquare::Occupied(Piece::King, _)  => {
-                    let is_left = pos_diff.0 < 0;
-                    if pos_diff.1 != 0 || pos_diff.0.abs() != 2 {
-                        false
-                    } else if (self.turn == Color::Black &&
-                        ((is_left && self.black_can_castle_left) || (!is_left && self.black_can_castle_right))) || 
-                        (self.turn == Color::White &&
-                            ((is_left && self.white_can_castle_left) || (!is_left && self.white_can_castle_right))) {
-                        let x = if is_left {0} else {7};
-                        // valid rook move really means that there's nothing between rook and king here.
-                        if self.is_valid_rook_move(from_pos, BoardPosition(x, from_pos.1)) {
-                            // Check for castling through or out of check
-                            !(self.is_square_attacked(from_pos, !self.turn) || self.is_square_attacked(BoardPosition((from_pos.0 as i64 + (pos_diff.0 / 2)) as usize, from_pos.1), !self.turn))
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                },
-                _ => false
-            };
-            if !could_be_legal {
-                return false;
-            }
+        moves
+    }
+    // Every legal move of the piece at from_pos, or nothing if it isn't the side to move's piece.
+    pub fn legal_moves_from(&self, from_pos: Square) -> Vec<Move> {
+        if !from_pos.is_on_board() {
+            return vec![];
         }
-        // Check legality of board state post-movement -- i.e. actually do the move, then see if our king becomes in check
-        let mut test_state = (*self).clone();
-        test_state.do_move(from_pos, to_pos);
-        let (pos, _) = test_state.piece_iterator().filter(|&(_, sq)| sq == BoardSquare::Occupied(Piece::King, self.turn)).next().unwrap();
-        if test_state.is_square_attacked(pos, !self.turn) {
-            false
-        } else {
-            true
+        let piece = match self.board_state[from_pos.1][from_pos.0] {
+            BoardSquare::Occupied(piece, color) if color == self.turn => piece,
+            _ => return vec![]
+        };
+        let mut moves = self.candidate_moves(from_pos, piece);
+        match self.variant {
+            Variant::Atomic => {
+                let mut scratch = self.clone();
+                moves.retain(|&mv| scratch.keeps_atomic_king(mv));
+            },
+            Variant::Antichess => if self.has_capture() {
+                moves.retain(|mv| mv.capture);
+            },
+            // Castling moves have had their king safety checked already
+            _ => moves.retain(|mv| mv.castle.is_some() || !self.leaves_king_attacked(mv.from, mv.to))
         }
+        moves
     }
-    fn is_square_attacked(&self, pos: BoardPosition, attacker: Color) -> bool {
-        if let BoardSquare::Occupied(_, color) = self.board_state[pos.1][pos.0] {
-            assert_ne!(color, attacker, "Color is same as attacker checking!");
-        }
-        for (piece_pos, boardsquare) in self.piece_iterator() {
-            if let BoardSquare::Occupied(_, color) = boardsquare {
-                if color != attacker {
-                    continue;
-                }
-                if self.is_attacking_movement_ok(piece_pos, pos, true) {
-                    return true;
-                }
+    // The moves of the side to move's piece at from_pos, castling included, before the mover's king is looked after.
+    fn candidate_moves(&self, from_pos: Square, piece: Piece) -> Vec<Move> {
+        let is_pawn = matches!(piece, Piece::Pawn(_));
+        let mut moves = vec![];
+        for to_pos in bitboard::squares(self.pseudo_targets(from_pos, piece)) {
+            let en_passant = is_pawn && from_pos.0 != to_pos.0 && self.board_state[to_pos.1][to_pos.0] == BoardSquare::Empty;
+            let capture = en_passant || self.board_state[to_pos.1][to_pos.0] != BoardSquare::Empty;
+            let mv = Move { from: from_pos, to: to_pos, promotion: None, capture, castle: None, en_passant };
+            if is_pawn && (to_pos.1 == 0 || to_pos.1 == 7) {
+                moves.extend(self.variant.promotion_pieces().iter().map(|&piece| Move { promotion: Some(piece), ..mv }));
             } else {
-                panic!("AAAAAAH");
+                moves.push(mv);
             }
         }
-        false
+        if piece == Piece::King {
+            moves.extend(self.castle_moves(from_pos));
+        }
+        moves
     }
-    fn is_valid_rook_move(&self, from_pos: BoardPosition, to_pos: BoardPosition) -> bool {
-        let pos_diff = ((to_pos.0 as i64 - from_pos.0 as i64), (to_pos.1 as i64 - from_pos.1 as i64));
-        if pos_diff.0 != 0 && pos_diff.1 != 0 {
-            false
-        } else {
-            if pos_diff.0 == 0 {
-                let start = cmp::min(to_pos.1, from_pos.1);
-                for i in start + 1..start + pos_diff.1.abs() as usize {
-                    if let BoardSquare::Occupied(_, _) = self.board_state[i][from_pos.0] {
-                        return false;
-                    }
-                }
-            } else {
-                let start = cmp::min(to_pos.0, from_pos.0);
-
-                for i in start + 1..start + pos_diff.0.abs() as usize {
-                    if let BoardSquare::Occupied(_, _) = self.board_state[from_pos.1][i] {
-                        return false;
-                    }
-                }
+    // Squares the side to move's piece at from_pos reaches, not yet checking that its own king stays safe.
+    fn pseudo_targets(&self, from_pos: Square, piece: Piece) -> Bitboard {
+        let sq = square_index(from_pos);
+        let occupied = self.bitboards.occupied();
+        let targets = match piece {
+            Piece::Pawn(has_moved) => self.pawn_targets(from_pos, has_moved),
+            Piece::Knight => bitboard::KNIGHT_ATTACKS[sq],
+            Piece::Bishop => bitboard::bishop_attacks(sq, occupied),
+            Piece::Rook => bitboard::rook_attacks(sq, occupied),
+            Piece::Queen => bitboard::bishop_attacks(sq, occupied) | bitboard::rook_attacks(sq, occupied),
+            // An atomic king can't capture, as it would blow itself up
+            Piece::King if self.variant == Variant::Atomic => bitboard::KING_ATTACKS[sq] & !occupied,
+            Piece::King => bitboard::KING_ATTACKS[sq]
+        };
+        targets & !self.bitboards.color(self.turn)
+    }
+    fn pawn_targets(&self, from_pos: Square, has_moved: bool) -> Bitboard {
+        let forward = |bb: Bitboard| if self.turn == Color::White {bb >> 8} else {bb << 8};
+        let empty = !self.bitboards.occupied();
+        let one_step = forward(square_bit(from_pos)) & empty;
+        let two_step = if has_moved {0} else {forward(one_step) & empty};
+        let mut enemies = self.bitboards.color(!self.turn);
+        // En passant lands behind the pawn that just double moved
+        if let Some(pos) = self.en_passant_square {
+            if enemies & square_bit(pos) != 0 {
+                enemies |= forward(square_bit(pos));
             }
-            true
         }
+        one_step | two_step | (bitboard::PAWN_ATTACKS[if self.turn == Color::White {0} else {1}][square_index(from_pos)] & enemies)
     }
-    fn is_valid_bishop_move(&self, from_pos: BoardPosition, to_pos: BoardPosition) -> bool {
-        let pos_diff = ((to_pos.0 as i64 - from_pos.0 as i64), (to_pos.1 as i64 - from_pos.1 as i64));
-        if pos_diff.0.abs() != pos_diff.1.abs() {
-            false
-        } else {
-            let i_dir : i64 = if pos_diff.0 > 0 {1} else {-1};
-            let j_dir : i64 = if pos_diff.1 > 0 {1} else {-1};
-            let mut pos = (from_pos.0 as i64 + i_dir, from_pos.1 as i64 + j_dir);
-            while pos.0 != to_pos.0 as i64 {
-                if let BoardSquare::Occupied(_, _) = self.board_state[pos.1 as usize][pos.0 as usize] {
-                    return false;
-                }
-                pos.0 += i_dir;
-                pos.1 += j_dir;
+    // Castling moves for the king on from_pos. Wherever they start, the king ends up on the g- or c-file with
+    // the rook beside it towards the middle. In Chess960 the move goes to the rook's square, so that it can't
+    // be mistaken for an ordinary king move.
+    fn castle_moves(&self, from_pos: Square) -> Vec<Move> {
+        let mut moves = vec![];
+        for side in [CastleSide::KingSide, CastleSide::QueenSide] {
+            let rook_pos = self.castling_rook(self.turn, side);
+            if !self.can_castle(self.turn, side) || from_pos.1 != rook_pos.1 || self.board_state[rook_pos.1][rook_pos.0] != BoardSquare::Occupied(Piece::Rook, self.turn) {
+                continue;
+            }
+            let (must_be_empty, king_path) = self.castling_paths(from_pos, side);
+            let others = self.bitboards.occupied() & !square_bit(from_pos) & !square_bit(rook_pos);
+            if must_be_empty & others != 0 {
+                continue;
+            }
+            // No castling out of, through or into check, and the castling rook doesn't shield the king
+            if bitboard::squares(king_path).any(|pos| self.is_attacked_through(pos, others)) {
+                continue;
             }
-            true
+            let to = if self.variant == Variant::Chess960 {rook_pos} else {castle_destinations(side, from_pos.1).0};
+            moves.push(Move { from: from_pos, to, promotion: None, capture: false, castle: Some(side), en_passant: false });
         }
+        moves
     }
-
-    // Assumes from_pos is occupied, and to_pos is occupied by opposite color or nothing
-    fn is_attacking_movement_ok(&self, from_pos: BoardPosition, to_pos: BoardPosition, pretend_occupied: bool) -> bool {
-        if let BoardSquare::Occupied(_, color) = self.board_state[from_pos.1][from_pos.0] {
-            if let BoardSquare::Occupied(_, color2) = self.board_state[to_pos.1][to_pos.0] {
-                if color == color2 {
-                    panic!("Attacking wrong color");
+    // For the side to move castling on side with its king on from_pos: the squares king and rook pass over or
+    // land on, which nothing else may stand on, and the squares of the king's way, start and end included.
+    fn castling_paths(&self, from_pos: Square, side: CastleSide) -> (Bitboard, Bitboard) {
+        let rook_pos = self.castling_rook(self.turn, side);
+        let (king_to, rook_to) = castle_destinations(side, from_pos.1);
+        let king_path = bitboard::between(square_index(from_pos), square_index(king_to)) | square_bit(from_pos) | square_bit(king_to);
+        let rook_path = bitboard::between(square_index(rook_pos), square_index(rook_to)) | square_bit(rook_to);
+        (king_path | rook_path, king_path)
+    }
+    fn can_castle(&self, color: Color, side: CastleSide) -> bool {
+        match (color, side) {
+            (Color::White, CastleSide::QueenSide) => self.white_can_castle_left,
+            (Color::White, CastleSide::KingSide) => self.white_can_castle_right,
+            (Color::Black, CastleSide::QueenSide) => self.black_can_castle_left,
+            (Color::Black, CastleSide::KingSide) => self.black_can_castle_right
+        }
+    }
+    fn castling_right_mut(&mut self, color: Color, side: CastleSide) -> &mut bool {
+        match (color, side) {
+            (Color::White, CastleSide::QueenSide) => &mut self.white_can_castle_left,
+            (Color::White, CastleSide::KingSide) => &mut self.white_can_castle_right,
+            (Color::Black, CastleSide::QueenSide) => &mut self.black_can_castle_left,
+            (Color::Black, CastleSide::KingSide) => &mut self.black_can_castle_right
+        }
+    }
+    // Where the rook that color castles with on side starts.
+    fn castling_rook(&self, color: Color, side: CastleSide) -> Square {
+        let x = self.castling_rooks[bitboard::color_index(color)][if side == CastleSide::QueenSide {0} else {1}];
+        Square(x, back_rank(color))
+    }
+    // A rook leaving the square it castles from, or taken there, gives up castling on its side.
+    fn drop_castling_rights_at(&mut self, pos: Square) {
+        for color in [Color::White, Color::Black] {
+            for side in [CastleSide::QueenSide, CastleSide::KingSide] {
+                if self.castling_rook(color, side) == pos {
+                    *self.castling_right_mut(color, side) = false;
                 }
             }
-        
-            let pos_diff = ((to_pos.0 as i64 - from_pos.0 as i64), (to_pos.1 as i64 - from_pos.1 as i64));
-            match self.board_state[from_pos.1][from_pos.0] {
-                BoardSquare::Occupied(Piece::Knight, _) => (pos_diff.0.abs() == 2 && pos_diff.1.abs() == 1) || (pos_diff.0.abs() == 1 && pos_diff.1.abs() == 2),
-                BoardSquare::Occupied(Piece::King, _) => pos_diff.0.abs() <= 1 && pos_diff.1.abs() <= 1,
-                BoardSquare::Occupied(Piece::Rook, _) => self.is_valid_rook_move(from_pos, to_pos),
-                BoardSquare::Occupied(Piece::Bishop, _) => self.is_valid_bishop_move(from_pos, to_pos),
-                BoardSquare::Occupied(Piece::Queen, _) => self.is_valid_bishop_move(from_pos, to_pos) || self.is_valid_rook_move(from_pos, to_pos),
-                // En passant is handled separately since it cannot attack a targeted square. Otherwise, pawns can only attack a real piece
-                BoardSquare::Occupied(Piece::Pawn(_), _) => {
-                    (pretend_occupied || matches!(self.board_state[to_pos.1][to_pos.0], BoardSquare::Occupied(_, _))) && pos_diff.0.abs() == 1 && ((pos_diff.1 == 1 && color == Color::Black) || (pos_diff.1 == -1 && color == Color::White))
-                },
-                _ => panic!("Invalid from square")
-            }
+        }
+    }
+    // Whether moving from_pos to to_pos would leave the mover's own king attacked.
+    fn leaves_king_attacked(&self, from_pos: Square, to_pos: Square) -> bool {
+        let moving = self.board_state[from_pos.1][from_pos.0];
+        let captured = if matches!(moving, BoardSquare::Occupied(Piece::Pawn(_), _)) && from_pos.0 != to_pos.0 && self.board_state[to_pos.1][to_pos.0] == BoardSquare::Empty {
+            // en passant removes a pawn that isn't on the target square
+            square_bit(Square(to_pos.0, from_pos.1))
         } else {
-            panic!("From empty square");
+            square_bit(to_pos)
+        };
+        let occupied = (self.bitboards.occupied() & !square_bit(from_pos) & !captured) | square_bit(to_pos);
+        let king_pos = if matches!(moving, BoardSquare::Occupied(Piece::King, _)) {Some(to_pos)} else {self.bitboards.king(self.turn)};
+        match king_pos {
+            Some(pos) => self.bitboards.attackers(square_index(pos), occupied) & self.bitboards.color(!self.turn) & !captured != 0,
+            None => false
         }
     }
-    pub fn is_legal_start(&self, pos: BoardPosition) -> bool {
+    fn is_square_attacked(&self, pos: Square, attacker: Color) -> bool {
+        self.bitboards.attackers(square_index(pos), self.bitboards.occupied()) & self.bitboards.color(attacker) != 0
+    }
+    // Whether the side not to move would attack pos if only the pieces in occupied were on the board.
+    fn is_attacked_through(&self, pos: Square, occupied: Bitboard) -> bool {
+        self.bitboards.attackers(square_index(pos), occupied) & self.bitboards.color(!self.turn) != 0
+    }
+    pub fn is_legal_start(&self, pos: Square) -> bool {
+        if !pos.is_on_board() {
+            return false;
+        }
         if let BoardSquare::Occupied(_, color) = self.board_state[pos.1][pos.0] {
             color == self.turn
         } else { 
             false
         }
     }
-    // Assumes legal move.
-	pub fn do_move(&mut self, from_pos: BoardPosition, to_pos: BoardPosition) -> () {
-        // reset this here so we can set it correctly if needed
-        let old_en_passant_square = self.en_passant_square;
-        self.en_passant_square = None;
-        // Special cases where weird stuff happens: Castle, en passant. Also, keep track of state
-        let pos_diff = ((to_pos.0 as i64 - from_pos.0 as i64), (to_pos.1 as i64 - from_pos.1 as i64));
-        match self.board_state[from_pos.1][from_pos.0] {
-            BoardSquare::Occupied(Piece::King, _) => {
-                if pos_diff.0.abs() == 2 {
-                    let rook_x = if pos_diff.0 < 0 {0} else {7};
-                    assert!(self.board_state[from_pos.1][rook_x] == BoardSquare::Occupied(Piece::Rook, self.turn));
-                    // move rook
-                    self.board_state[to_pos.1][(to_pos.0 as i64 - (pos_diff.0 / 2)) as usize] = BoardSquare::Occupied(Piece::Rook, self.turn);
-                    self.board_state[from_pos.1][rook_x] = BoardSquare::Empty;
-                }
-                // update castling vars
-                if self.turn == Color::White {
-                    self.white_can_castle_left = false;
-                    self.white_can_castle_right = false;
-                } else {
-                    self.black_can_castle_left = false;
-                    self.black_can_castle_right = false;
-                }
-                self.board_state[to_pos.1][to_pos.0] = self.board_state[from_pos.1][from_pos.0];
-                self.board_state[from_pos.1][from_pos.0] = BoardSquare::Empty;
-            },
-            BoardSquare::Occupied(Piece::Pawn(_), _) => {
-                // Make sure we set has_moved so we can't double move, plus set en passant, plus handle en passant
-                // Double move should set en_passant_square to this
-                if pos_diff.1.abs() == 2 {
-                    self.en_passant_square = Some(to_pos);
-                } else if pos_diff.0.abs() == 1 && self.board_state[to_pos.1][to_pos.0] == BoardSquare::Empty {
-                    // en passanting
-                    let pass_pos = old_en_passant_square.unwrap();
-                    self.board_state[pass_pos.1][pass_pos.0] = BoardSquare::Empty;
-                }
-                self.board_state[to_pos.1][to_pos.0] = BoardSquare::Occupied(Piece::Pawn(true), self.turn);
-                self.board_state[from_pos.1][from_pos.0] = BoardSquare::Empty;
-            },
-            BoardSquare::Occupied(Piece::Rook, _) => {
-                // update castling vars
-                let back_rank = if self.turn == Color::White {7} else {0};
-                if from_pos.1 == back_rank {
-                    if from_pos.0 == 0 {
-                        if self.turn == Color::White {
-                            self.white_can_castle_left = false;
-                        } else {
-                            self.black_can_castle_left = false;
-                        }
-                    } else if from_pos.0 == 7 {
-                        if self.turn == Color::White {
-                            self.white_can_castle_right = false;
-                        } else {
-                            self.black_can_castle_right = false;
-                        }
-                    }
-                }
-                self.board_state[to_pos.1][to_pos.0] = self.board_state[from_pos.1][from_pos.0];
-                self.board_state[from_pos.1][from_pos.0] = BoardSquare::Empty;
-            }, 
-            _ => {
-                self.board_state[to_pos.1][to_pos.0] = self.board_state[from_pos.1][from_pos.0];
-                self.board_state[from_pos.1][from_pos.0] = BoardSquare::Empty;
-            }  
+    // Assumes mv is legal in this position, e.g. one from legal_moves.
+	pub fn do_move(&mut self, mv: Move) -> () {
+        self.redo_stack.clear();
+        self.make_move(mv);
+    }
+    fn make_move(&mut self, mv: Move) {
+        let (from_pos, to_pos) = (mv.from, mv.to);
+        let moved = self.board_state[from_pos.1][from_pos.0];
+        let captured_pos = mv.captured_square();
+        let mut info = UndoInfo {
+            mv, moved,
+            captured: if mv.castle.is_some() {BoardSquare::Empty} else {self.board_state[captured_pos.1][captured_pos.0]},
+            castling: (self.black_can_castle_left, self.black_can_castle_right, self.white_can_castle_left, self.white_can_castle_right),
+            en_passant_square: self.en_passant_square,
+            halfmove_clock: self.halfmove_clock,
+            checks_given: self.checks_given,
+            exploded: vec![],
+        };
+        let touched = self.touched_squares(&mv);
+        self.toggle_hash(&touched);
+        let is_pawn = matches!(moved, BoardSquare::Occupied(Piece::Pawn(_), _));
+        // Only a pawn's double step can be taken en passant, and only straight after
+        self.en_passant_square = if is_pawn && from_pos.1.abs_diff(to_pos.1) == 2 {Some(to_pos)} else {None};
+        if let Some(side) = mv.castle {
+            // Both are lifted before either is put down, as in Chess960 one may land where the other stood
+            let rook_pos = self.castling_rook(self.turn, side);
+            let rook = self.board_state[rook_pos.1][rook_pos.0];
+            let (king_to, rook_to) = castle_destinations(side, from_pos.1);
+            self.set_square(from_pos, BoardSquare::Empty);
+            self.set_square(rook_pos, BoardSquare::Empty);
+            self.set_square(king_to, moved);
+            self.set_square(rook_to, rook);
+        } else {
+            if mv.en_passant {
+                self.set_square(captured_pos, BoardSquare::Empty);
+            }
+            let placed = match (mv.promotion, moved) {
+                (Some(piece), _) => BoardSquare::Occupied(piece, self.turn),
+                // A pawn that has moved can't double move any more
+                (None, BoardSquare::Occupied(Piece::Pawn(_), color)) => BoardSquare::Occupied(Piece::Pawn(true), color),
+                (None, _) => moved
+            };
+            self.set_square(to_pos, placed);
+            self.set_square(from_pos, BoardSquare::Empty);
+            if mv.capture && self.variant == Variant::Atomic {
+                info.exploded = self.explode(to_pos);
+            }
+        }
+        // update castling vars: a king moving or blown up gives up both sides, a rook leaving its square or taken there gives up its side
+        let lost_kings = info.exploded.iter().filter_map(|&(_, sq)| match sq {
+            BoardSquare::Occupied(Piece::King, color) => Some(color),
+            _ => None
+        });
+        let moved_king = if matches!(moved, BoardSquare::Occupied(Piece::King, _)) {Some(self.turn)} else {None};
+        for color in moved_king.into_iter().chain(lost_kings).collect::<Vec<Color>>() {
+            for side in [CastleSide::QueenSide, CastleSide::KingSide] {
+                *self.castling_right_mut(color, side) = false;
+            }
+        }
+        for pos in [from_pos, to_pos].into_iter().chain(info.exploded.iter().map(|&(pos, _)| pos)).collect::<Vec<Square>>() {
+            self.drop_castling_rights_at(pos);
         }
         // Update turn
+        if self.turn == Color::Black {
+            self.fullmove_number += 1;
+        }
         self.turn = !self.turn;
+        if self.variant == Variant::ThreeCheck && self.is_in_check() {
+            self.checks_given[bitboard::color_index(!self.turn)] += 1;
+        }
+        // Update fifty-move and repetition tracking
+        self.halfmove_clock = if is_pawn || mv.capture {0} else {self.halfmove_clock + 1};
+        self.toggle_hash(&touched);
+        self.position_history.push(self.hash);
+        self.undo_stack.push(info);
+    }
+
+    // Take back the last move, returning it, or None if there is nothing to take back.
+    pub fn undo(&mut self) -> Option<Move> {
+        let undone = self.unmake_move()?;
+        self.redo_stack.push(undone);
+        Some(undone)
+    }
+    fn unmake_move(&mut self) -> Option<Move> {
+        let info = self.undo_stack.pop()?;
+        let mv = info.mv;
+        let touched = self.touched_squares(&mv);
+        self.toggle_hash(&touched);
+        self.turn = !self.turn;
+        if self.turn == Color::Black {
+            self.fullmove_number -= 1;
+        }
+        for &(pos, sq) in info.exploded.iter() {
+            self.set_square(pos, sq);
+        }
+        if let Some(side) = mv.castle {
+            let rook_pos = self.castling_rook(self.turn, side);
+            let (king_to, rook_to) = castle_destinations(side, mv.from.1);
+            let rook = self.board_state[rook_to.1][rook_to.0];
+            self.set_square(king_to, BoardSquare::Empty);
+            self.set_square(rook_to, BoardSquare::Empty);
+            self.set_square(rook_pos, rook);
+            self.set_square(mv.from, info.moved);
+        } else {
+            self.set_square(mv.to, BoardSquare::Empty);
+            self.set_square(mv.captured_square(), info.captured);
+            self.set_square(mv.from, info.moved);
+        }
+        (self.black_can_castle_left, self.black_can_castle_right, self.white_can_castle_left, self.white_can_castle_right) = info.castling;
+        self.en_passant_square = info.en_passant_square;
+        self.halfmove_clock = info.halfmove_clock;
+        self.checks_given = info.checks_given;
+        self.toggle_hash(&touched);
+        self.position_history.pop();
+        Some(mv)
+    }
+
+    // Replay the last move taken back, returning it, or None if there is nothing to replay.
+    pub fn redo(&mut self) -> Option<Move> {
+        let mv = self.redo_stack.pop()?;
+        self.make_move(mv);
+        Some(mv)
+    }
+
+    // Every move played since the position was set up, oldest first.
+    pub fn move_history(&self) -> Vec<Move> {
+        self.undo_stack.iter().map(|info| info.mv).collect()
+    }
+
+    // The move that led to this position, if any has been played since it was set up.
+    pub fn last_move(&self) -> Option<Move> {
+        self.undo_stack.last().map(|info| info.mv)
     }
 
     pub fn is_checkmate(&mut self, attacker: Color) -> bool {
-        let (pos, _) = self.piece_iterator().filter(|&(_, piece)| piece == BoardSquare::Occupied(Piece::King, !attacker)).next().unwrap();
-        if !self.is_square_attacked(pos, attacker) {
-            return false;
+        // A side without a king can't be mated
+        match self.bitboards.king(!attacker) {
+            Some(pos) if self.is_square_attacked(pos, attacker) => {},
+            _ => return false
         }
-        let old_turn = self.turn.clone();
+        let old_turn = self.turn;
         self.turn = !attacker;
-        let mut is_mate = true;
-        'outer: for (defender_pos, defender_sq) in self.piece_iterator() {
-            if let BoardSquare::Occupied(_, color) = defender_sq {
-                if color == attacker {
-                    continue;
-                }
-                for target_sq in (0..64).map(|x| BoardPosition(x / 8, x % 8)) {
-                    if self.is_legal(defender_pos, target_sq) {
-                        is_mate = false;
-                        break 'outer;
-                    }
-                }
-            }
-        }
+        let is_mate = !self.has_legal_move();
         self.turn = old_turn;
         is_mate
     }
-}
 
-fn c_to_sq(c: char) -> BoardSquare {
-    let color = if c.is_ascii_lowercase() { Color::Black } else { Color::White };
-    match c.to_ascii_lowercase() {
-        '.' => BoardSquare::Empty,
-        'p' => BoardSquare::Occupied(Piece::Pawn(false), color),
-        'r' => BoardSquare::Occupied(Piece::Rook, color),
-        'b' => BoardSquare::Occupied(Piece::Bishop, color),
-        'n' => BoardSquare::Occupied(Piece::Knight, color),
-        'q' => BoardSquare::Occupied(Piece::Queen, color),
-        'k' => BoardSquare::Occupied(Piece::King, color),
-        _ => panic!("Nonexistent c")
+    fn has_legal_move(&self) -> bool {
+        self.piece_iterator().any(|(from_pos, _)| !self.legal_moves_from(from_pos).is_empty())
+    }
+
+    // Neither side can mate: bare kings, a single minor piece, or only bishops that all stand on one square color.
+    // Where mate isn't the only way to win, only bare kings in Three-check and Atomic, and never in the others.
+    fn is_insufficient_material(&self) -> bool {
+        match self.variant {
+            Variant::Standard | Variant::Chess960 => {},
+            Variant::ThreeCheck | Variant::Atomic => return self.bitboards.occupied().count_ones() == 2,
+            Variant::KingOfTheHill | Variant::Antichess => return false
+        }
+        let mut minors = vec![];
+        for (pos, sq) in self.piece_iterator() {
+            match sq {
+                BoardSquare::Occupied(Piece::King, _) => {},
+                BoardSquare::Occupied(Piece::Knight, _) | BoardSquare::Occupied(Piece::Bishop, _) => minors.push((pos, sq)),
+                _ => return false
+            }
+        }
+        match minors.len() {
+            0 | 1 => true,
+            _ => minors.iter().all(|&(pos, sq)| {
+                matches!(sq, BoardSquare::Occupied(Piece::Bishop, _)) && (pos.0 + pos.1) % 2 == (minors[0].0.0 + minors[0].0.1) % 2
+            })
+        }
+    }
+
+    // How many times the current position has occurred, counting only since the last irreversible move.
+    fn repetition_count(&self) -> usize {
+        let current = match self.position_history.last() {
+            Some(key) => *key,
+            None => return 1
+        };
+        self.position_history.iter().rev().take(self.halfmove_clock + 1).filter(|&&key| key == current).count()
+    }
+
+    // None while the game is still going.
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        if let Some(outcome) = self.variant_outcome() {
+            return Some(outcome);
+        }
+        if !self.has_legal_move() {
+            return Some(if self.variant == Variant::Antichess {
+                GameOutcome::NoMovesLeft { winner: self.turn }
+            } else if self.is_in_check() {
+                GameOutcome::Checkmate { winner: !self.turn }
+            } else {
+                GameOutcome::Stalemate
+            });
+        }
+        if self.is_insufficient_material() {
+            Some(GameOutcome::InsufficientMaterial)
+        } else if self.halfmove_clock >= 100 {
+            Some(GameOutcome::FiftyMoveRule)
+        } else if self.repetition_count() >= 3 {
+            Some(GameOutcome::ThreefoldRepetition)
+        } else {
+            None
+        }
     }
 }
 
-const default_state: &str = "\
-rnbqkbnr\
-pppppppp\
-........\
-........\
-........\
-........\
-PPPPPPPP\
-RNBQKBNR";
-
-const base_dir: &str = "/Users/gabriel.marks/my-project/images/";
-
-const piece_imagepaths: [(char, &str); 12] = [
-    ('p', "blackpawn.png"),
-    ('r', "blackrook.png"),
-    ('b', "blackbishop.png"),
-    ('n', "blackknight.png"),
-    ('q', "blackqueen.png"),
-    ('k', "blackking.png"),
-    ('P', "whitepawn.png"),
-    ('R', "whiterook.png"),
-    ('B', "whitebishop.png"),
-    ('N', "whiteknight.png"),
-    ('Q', "whitequeen.png"),
-    ('K', "whiteking.png"),
-];
-
-pub struct Board {
-	width: f64,
-    height: f64,
-	pub game_state: GameState,
-	pieces: HashMap<BoardSquare, Box<dyn Drawable>>,
-    pub highlight: Option<BoardPosition>
+// The row of color's first rank.
+fn back_rank(color: Color) -> usize {
+    if color == Color::White {7} else {0}
 }
 
-impl Board {
-    pub fn new(width: f64, height: f64) -> Self {
-        let game_state = GameState::new();
-        let mut pieces : HashMap<BoardSquare, Box<dyn Drawable>> = HashMap::new();
-        for (c, path) in piece_imagepaths {
-            pieces.insert(c_to_sq(c), Box::new(PngDrawable::new(&format!("{}{}", base_dir, path), width / 8.0, height / 8.0).unwrap()));
-        }
-        pieces.insert(BoardSquare::Empty, Box::new(EmptyDrawable{}));
-        Board {width, height, game_state, pieces, highlight: None}
+// Where king and rook end up after castling on side along the given back rank: the g- and f-files or the c- and
+// d-files, wherever they started.
+fn castle_destinations(side: CastleSide, rank: usize) -> (Square, Square) {
+    match side {
+        CastleSide::KingSide => (Square(6, rank), Square(5, rank)),
+        CastleSide::QueenSide => (Square(2, rank), Square(3, rank))
     }
-    
-    pub fn setup_new_game(&mut self) -> () {
-        self.setup_set_game(default_state, Color::White, true, true, true, true, None);
+}
+
+const KNIGHT_OFFSETS: [(i64, i64); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_OFFSETS: [(i64, i64); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+const ROOK_DIRECTIONS: [(i64, i64); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+const BISHOP_DIRECTIONS: [(i64, i64); 4] = [(1, 1), (1, -1), (-1, -1), (-1, 1)];
+
+// pos shifted by diff, if that is still on the board.
+fn offset(pos: Square, diff: (i64, i64)) -> Option<Square> {
+    let (x, y) = (pos.0 as i64 + diff.0, pos.1 as i64 + diff.1);
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some(Square(x as usize, y as usize))
+    } else {
+        None
     }
+}
 
-    pub fn setup_set_game(&mut self, state: &str, turn: Color, bcl: bool, bcr: bool, wcl: bool, wcr: bool, eps: Option<BoardPosition>) -> () {
-        assert_eq!(state.len(), 64);
-        let mut state_it = state.chars();
-        for i in 0..8 {
-            for c in 0..8 {
-                self.game_state.board_state[i][c] = c_to_sq(state_it.next().unwrap());
-            }    
+fn char_to_sq(c: char) -> Option<BoardSquare> {
+    let color = if c.is_ascii_lowercase() { Color::Black } else { Color::White };
+    match c.to_ascii_lowercase() {
+        '.' => Some(BoardSquare::Empty),
+        'p' => Some(BoardSquare::Occupied(Piece::Pawn(false), color)),
+        'r' => Some(BoardSquare::Occupied(Piece::Rook, color)),
+        'b' => Some(BoardSquare::Occupied(Piece::Bishop, color)),
+        'n' => Some(BoardSquare::Occupied(Piece::Knight, color)),
+        'q' => Some(BoardSquare::Occupied(Piece::Queen, color)),
+        'k' => Some(BoardSquare::Occupied(Piece::King, color)),
+        _ => None
+    }
+}
+
+fn sq_to_char(sq: BoardSquare) -> char {
+    match sq {
+        BoardSquare::Empty => '.',
+        BoardSquare::Occupied(piece, color) => {
+            let c = match piece {
+                Piece::Pawn(_) => 'p',
+                Piece::Knight => 'n',
+                Piece::Bishop => 'b',
+                Piece::Rook => 'r',
+                Piece::Queen => 'q',
+                Piece::King => 'k'
+            };
+            if color == Color::White { c.to_ascii_uppercase() } else { c }
         }
-        self.game_state.turn = turn;
-        self.game_state.black_can_castle_left = bcl;
-        self.game_state.black_can_castle_right = bcr;
-        self.game_state.white_can_castle_left = wcl;
-        self.game_state.white_can_castle_right = wcr;
-        self.game_state.en_passant_square = eps;
     }
+}
 
-    pub fn is_checkmated(&mut self) -> bool {
-        self.game_state.is_checkmate(!self.game_state.turn)
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Why a FEN string could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FenError {
+    reason: String
+}
+impl FenError {
+    fn new(reason: impl Into<String>) -> Self {
+        FenError { reason: reason.into() }
     }
 }
-const light_color: (f64, f64, f64) = (180.0 / 255.0, 175.0 / 255.0, 165.0 / 255.0);
-const dark_color: (f64, f64, f64) = (145.0 / 255.0, 140.0 / 255.0, 125.0 / 255.0);
-const highlight_color: (f64, f64, f64) = (180.0 / 255.0, 80.0 / 255.0, 80.0 / 255.0);
-impl Drawable for Board {
-    fn draw(&self, ctx: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {
-        ctx.save()?;
-        ctx.translate(position.0, position.0);
-        ctx.set_source_rgb(light_color.0, light_color.1, light_color.2);
-        ctx.rectangle(0.0, 0.0, self.width, self.height);
-        ctx.fill()?;
-        let mut first_light = true;
-        let tile_w = self.width / 8.0;
-        let tile_h = self.height / 8.0;
-        ctx.set_source_rgb(dark_color.0, dark_color.1, dark_color.2);
-        for i in 0..8 {
-            let start = if first_light {1} else {0};
-            for j in (start..8).step_by(2) {
-                ctx.rectangle(j as f64 * tile_w, i as f64 * tile_h, tile_w.ceil(), tile_h.ceil());
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid FEN: {}", self.reason)
+    }
+}
+impl std::error::Error for FenError {}
+
+impl GameState {
+    // Load a position from Forsyth-Edwards Notation. The halfmove and fullmove counters may be left off.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        GameState::from_variant_fen(Variant::Standard, fen)
+    }
+
+    // Load a position of the given variant. Three-check positions may give the checks each side still
+    // needs, e.g. "3+3", after the en passant square.
+    pub fn from_variant_fen(variant: Variant, fen: &str) -> Result<Self, FenError> {
+        let mut fields: Vec<&str> = fen.split_whitespace().collect();
+        let mut state = GameState::new();
+        state.variant = variant;
+        if variant == Variant::ThreeCheck && fields.get(4).is_some_and(|field| field.contains('+')) {
+            let checks_left = fields.remove(4);
+            let parsed = checks_left.split_once('+').and_then(|(white, black)| Some([white.parse::<u8>().ok()?, black.parse::<u8>().ok()?]));
+            match parsed {
+                Some(left) if left.iter().all(|&n| n <= variant::CHECKS_TO_WIN) => {
+                    state.checks_given = left.map(|n| variant::CHECKS_TO_WIN - n);
+                },
+                _ => return Err(FenError::new(format!("bad checks left '{}'", checks_left)))
             }
-            first_light = !first_light;
         }
-        ctx.fill()?;
-        if let Some(hl_pos) = self.highlight {
-            ctx.set_source_rgb(highlight_color.0, highlight_color.1, highlight_color.2);
-            ctx.rectangle(hl_pos.0 as f64 * tile_w, hl_pos.1 as f64 * tile_h, tile_w.ceil(), tile_h.ceil());
-            ctx.fill()?;
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(FenError::new(format!("expected 4 or 6 fields, got {}", fields.len())));
         }
-        for i in 0..8 {
-            for j in 0..8 {
-                let bs = &self.game_state.board_state[i][j];
-                let piece_draw;
-                if let BoardSquare::Occupied(Piece::Pawn(true), color) = *bs {
-                    piece_draw = self.pieces.get(&BoardSquare::Occupied(Piece::Pawn(false), color)).unwrap();
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::new(format!("expected 8 ranks, got {}", ranks.len())));
+        }
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(skip) = c.to_digit(10).filter(|&d| (1..=8).contains(&d)) {
+                    x += skip as usize;
                 } else {
-                    piece_draw = self.pieces.get(bs).unwrap();
+                    let sq = char_to_sq(c).filter(|&sq| sq != BoardSquare::Empty)
+                        .ok_or_else(|| FenError::new(format!("unknown piece '{}'", c)))?;
+                    if x < 8 {
+                        state.set_square(Square(x, y), match sq {
+                            // Pawns off their starting rank must have moved, so can't double move
+                            BoardSquare::Occupied(Piece::Pawn(_), color) => BoardSquare::Occupied(Piece::Pawn(y != if color == Color::White {6} else {1}), color),
+                            _ => sq
+                        });
+                    }
+                    x += 1;
                 }
-                piece_draw.draw(ctx, ScreenPosition(j as f64 * tile_w, i as f64 * tile_h))?;
+                if x > 8 {
+                    break;
+                }
+            }
+            if x != 8 {
+                return Err(FenError::new(format!("rank {} does not have 8 squares", 8 - y)));
             }
         }
-        ctx.restore()?;
-        Ok(())
+        for color in [Color::White, Color::Black] {
+            let kings = state.bitboards.pieces(Piece::King, color).count_ones();
+            // Antichess kings are ordinary pieces, and an atomic king may have been blown up
+            let allowed = match variant {
+                Variant::Antichess => true,
+                Variant::Atomic => kings <= 1,
+                _ => kings == 1
+            };
+            if !allowed {
+                return Err(FenError::new(format!("{:?} has {} kings", color, kings)));
+            }
+        }
+
+        state.turn = match fields[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => return Err(FenError::new(format!("unknown side to move '{}'", other)))
+        };
+
+        // KQkq stand for the outermost rook on that side of the king, or as in Shredder-FEN a right can be
+        // given by the rook's file, which Chess960 needs when two rooks stand on one side. Rights that the
+        // king and rook placement can't back up are dropped, and Antichess has none.
+        state.white_can_castle_left = false;
+        state.white_can_castle_right = false;
+        state.black_can_castle_left = false;
+        state.black_can_castle_right = false;
+        if fields[2] != "-" {
+            for c in fields[2].chars() {
+                let color = if c.is_ascii_uppercase() {Color::White} else {Color::Black};
+                let rank = back_rank(color);
+                let rooks: Vec<usize> = (0..8).filter(|&x| state.board_state[rank][x] == BoardSquare::Occupied(Piece::Rook, color)).collect();
+                let king_x = state.bitboards.king(color).filter(|pos| pos.1 == rank).map(|pos| pos.0);
+                let (side, rook_x) = match c.to_ascii_lowercase() {
+                    'k' => (CastleSide::KingSide, rooks.last().copied()),
+                    'q' => (CastleSide::QueenSide, rooks.first().copied()),
+                    file @ 'a'..='h' => {
+                        let x = file as usize - 'a' as usize;
+                        (if king_x.is_some_and(|king_x| x < king_x) {CastleSide::QueenSide} else {CastleSide::KingSide}, rooks.contains(&x).then_some(x))
+                    },
+                    _ => return Err(FenError::new(format!("unknown castling right '{}'", c)))
+                };
+                let backed = king_x.zip(rook_x).filter(|&(king_x, rook_x)| {
+                    (if side == CastleSide::KingSide {rook_x > king_x} else {rook_x < king_x})
+                        && (variant == Variant::Chess960 || (king_x == 4 && rook_x == if side == CastleSide::KingSide {7} else {0}))
+                });
+                if let Some((_, rook_x)) = backed.filter(|_| variant != Variant::Antichess) {
+                    state.castling_rooks[bitboard::color_index(color)][if side == CastleSide::QueenSide {0} else {1}] = rook_x;
+                    *state.castling_right_mut(color, side) = true;
+                }
+            }
+        }
+
+        // FEN names the square behind the pawn, we keep the pawn itself
+        if fields[3] != "-" {
+            let target: Square = fields[3].parse().map_err(|_| FenError::new(format!("bad en passant square '{}'", fields[3])))?;
+            let (target_rank, pawn_dir) = if state.turn == Color::White {(2, 1)} else {(5, -1)};
+            let pawn_pos = offset(target, (0, pawn_dir));
+            match pawn_pos {
+                Some(pos) if target.1 == target_rank && state.board_state[pos.1][pos.0] == BoardSquare::Occupied(Piece::Pawn(true), !state.turn) => {
+                    state.en_passant_square = Some(pos);
+                },
+                _ => return Err(FenError::new(format!("no pawn can be taken en passant on {}", fields[3])))
+            }
+        }
+
+        state.reset_history();
+        if fields.len() == 6 {
+            state.halfmove_clock = fields[4].parse().map_err(|_| FenError::new(format!("bad halfmove clock '{}'", fields[4])))?;
+            state.fullmove_number = fields[5].parse().ok().filter(|&n| n > 0)
+                .ok_or_else(|| FenError::new(format!("bad fullmove number '{}'", fields[5])))?;
+        }
+        Ok(state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut ranks = vec![];
+        for row in self.board_state.iter() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for sq in row.iter() {
+                if *sq == BoardSquare::Empty {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                rank.push(sq_to_char(*sq));
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+        // Chess960 names the rooks' files, as in Shredder-FEN
+        let mut castling = String::new();
+        for color in [Color::White, Color::Black] {
+            for side in [CastleSide::KingSide, CastleSide::QueenSide] {
+                if !self.can_castle(color, side) {
+                    continue;
+                }
+                let c = match side {
+                    _ if self.variant == Variant::Chess960 => (b'a' + self.castling_rook(color, side).0 as u8) as char,
+                    CastleSide::KingSide => 'k',
+                    CastleSide::QueenSide => 'q'
+                };
+                castling.push(if color == Color::White {c.to_ascii_uppercase()} else {c});
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        let en_passant = match self.en_passant_square {
+            Some(pos) => Square(pos.0, if pos.1 == 3 {2} else {5}).to_string(),
+            None => "-".to_string()
+        };
+        let checks_left = if self.variant == Variant::ThreeCheck {
+            format!(" {}+{}", variant::CHECKS_TO_WIN.saturating_sub(self.checks_given[0]), variant::CHECKS_TO_WIN.saturating_sub(self.checks_given[1]))
+        } else {
+            String::new()
+        };
+        let turn = if self.turn == Color::White {"w"} else {"b"};
+        format!("{} {} {} {}{} {} {}", ranks.join("/"), turn, castling, en_passant, checks_left, self.halfmove_clock, self.fullmove_number)
     }
 }
-	
 #[cfg(test)]
 mod tests {
     use super::*;
+    fn state_from(state: &str, turn: Color) -> GameState {
+        let mut game_state = GameState::new();
+        let mut state_it = state.chars();
+        for i in 0..8 {
+            for c in 0..8 {
+                game_state.set_square(Square(c, i), char_to_sq(state_it.next().unwrap()).unwrap());
+            }
+        }
+        game_state.turn = turn;
+        game_state.black_can_castle_left = false;
+        game_state.black_can_castle_right = false;
+        game_state.white_can_castle_left = false;
+        game_state.white_can_castle_right = false;
+        game_state.reset_history();
+        game_state
+    }
+
     #[test]
-    fn test_checkmate() -> Result<(), &'static str> {
-        let mut board = Board::new(0.0, 0.0);
-        board.setup_set_game(&format!("R...k...R.......K{}", ".".repeat(64-17)), Color::Black, false, false, false, false, None);
-        assert!(board.is_checkmated());
-        board.setup_set_game(&format!("R...k...B.......K{}", ".".repeat(64-17)), Color::Black, false, false, false, false, None);
-        assert!(!board.is_checkmated());
-        board.setup_set_game(&format!("R...k...R.......K{}", ".".repeat(64-17)), Color::White, false, false, false, false, None);
-        assert!(!board.is_checkmated());
-        Ok(())
+    fn test_promotion() {
+        let state = state_from(&format!(".r..k...P{}.K.....", ".".repeat(64-16)), Color::White);
+        // Every move onto the last rank is a promotion, once for each piece
+        let moves = state.legal_moves_from(Square(0, 1));
+        assert_eq!(moves.len(), 8);
+        assert!(moves.iter().all(|mv| mv.promotion.is_some()));
+        assert!(state.find_move(Square(0, 1), Square(0, 0), None).is_none());
+        assert_eq!(state.find_move(Square(2, 7), Square(2, 6), None).unwrap().promotion, None);
+        for piece in PROMOTION_PIECES {
+            let mut promoted = state.clone();
+            promoted.do_move(promoted.find_move(Square(0, 1), Square(0, 0), Some(piece)).unwrap());
+            assert_eq!(promoted.board_state[0][0], BoardSquare::Occupied(piece, Color::White));
+            assert_eq!(promoted.board_state[1][0], BoardSquare::Empty);
+        }
+        // Capturing onto the last rank promotes too
+        let mut promoted = state.clone();
+        let capture = promoted.find_move(Square(0, 1), Square(1, 0), Some(Piece::Queen)).unwrap();
+        assert!(capture.capture);
+        promoted.do_move(capture);
+        assert_eq!(promoted.board_state[0][1], BoardSquare::Occupied(Piece::Queen, Color::White));
+    }
+
+    #[test]
+    fn test_outcome() {
+        let empty = ".".repeat(64);
+        // Black king in the corner with no moves but not in check
+        let stalemate = state_from(&format!("k.......{}.Q......{}K", ".".repeat(8), ".".repeat(39)), Color::Black);
+        assert_eq!(stalemate.outcome(), Some(GameOutcome::Stalemate));
+        let mate = state_from(&format!("R...k...R.......K{}", ".".repeat(64-17)), Color::Black);
+        assert_eq!(mate.outcome(), Some(GameOutcome::Checkmate { winner: Color::White }));
+        assert_eq!(mate.outcome().unwrap().winner(), Some(Color::White));
+
+        let mut bare = state_from(&empty, Color::White);
+        bare.set_square(Square(0, 0), BoardSquare::Occupied(Piece::King, Color::Black));
+        bare.set_square(Square(7, 7), BoardSquare::Occupied(Piece::King, Color::White));
+        assert_eq!(bare.outcome(), Some(GameOutcome::InsufficientMaterial));
+        // Bishops on the same square color can't mate, on opposite colors they can
+        bare.set_square(Square(5, 7), BoardSquare::Occupied(Piece::Bishop, Color::White));
+        bare.set_square(Square(2, 0), BoardSquare::Occupied(Piece::Bishop, Color::Black));
+        assert_eq!(bare.outcome(), Some(GameOutcome::InsufficientMaterial));
+        bare.set_square(Square(2, 0), BoardSquare::Empty);
+        bare.set_square(Square(3, 0), BoardSquare::Occupied(Piece::Bishop, Color::Black));
+        assert_eq!(bare.outcome(), None);
+        bare.set_square(Square(3, 0), BoardSquare::Occupied(Piece::Rook, Color::Black));
+        assert_eq!(bare.outcome(), None);
+
+        // Shuffle the knights back and forth until the starting position has appeared three times
+        let mut repeated = state_from(&format!(".n..k...{}.N..K...", ".".repeat(48)), Color::White);
+        let shuffle = [(Square(1, 7), Square(2, 5)), (Square(1, 0), Square(2, 2)),
+                       (Square(2, 5), Square(1, 7)), (Square(2, 2), Square(1, 0))];
+        for (from_pos, to_pos) in shuffle.iter().chain(shuffle.iter()) {
+            assert_eq!(repeated.outcome(), None);
+            repeated.do_move(repeated.find_move(*from_pos, *to_pos, None).unwrap());
+        }
+        assert_eq!(repeated.outcome(), Some(GameOutcome::ThreefoldRepetition));
+        assert_eq!(repeated.halfmove_clock, 8);
+
+        let mut fifty = state_from(&format!("r...k...{}R...K...", ".".repeat(48)), Color::White);
+        fifty.halfmove_clock = 99;
+        fifty.do_move(fifty.find_move(Square(0, 7), Square(1, 7), None).unwrap());
+        assert_eq!(fifty.outcome(), Some(GameOutcome::FiftyMoveRule));
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(start.legal_moves().len(), 20);
+        let targets = |moves: Vec<Move>| moves.iter().map(|mv| mv.to).collect::<Vec<Square>>();
+        assert_eq!(targets(start.legal_moves_from(Square(6, 7))), vec![Square(5, 5), Square(7, 5)]);
+        assert!(start.legal_moves_from(Square(4, 1)).is_empty());
+
+        // Both castles available, until a rook covers f1
+        let mut castling = state_from(&format!("r...k..r{}R...K..R", ".".repeat(48)), Color::White);
+        castling.white_can_castle_left = true;
+        castling.white_can_castle_right = true;
+        let king_moves = castling.legal_moves_from(Square(4, 7));
+        assert_eq!(king_moves.len(), 7);
+        assert_eq!(castling.find_move(Square(4, 7), Square(2, 7), None).unwrap().castle, Some(CastleSide::QueenSide));
+        assert_eq!(castling.find_move(Square(4, 7), Square(6, 7), None).unwrap().castle, Some(CastleSide::KingSide));
+        castling.set_square(Square(5, 0), BoardSquare::Occupied(Piece::Rook, Color::Black));
+        let king_moves = targets(castling.legal_moves_from(Square(4, 7)));
+        assert!(king_moves.contains(&Square(2, 7)) && !king_moves.contains(&Square(6, 7)));
+        assert!(!king_moves.contains(&Square(5, 6)));
+
+        // En passant right after the double move, but not a move later
+        let mut passant = state_from(&format!("....k......p{}P...{}K...", ".".repeat(16), ".".repeat(31)), Color::Black);
+        passant.do_move(passant.find_move(Square(3, 1), Square(3, 3), None).unwrap());
+        let en_passant = passant.find_move(Square(4, 3), Square(3, 2), None).unwrap();
+        assert!(en_passant.en_passant && en_passant.capture);
+        let mut taken = passant.clone();
+        taken.do_move(en_passant);
+        assert_eq!(taken.board_state[3][3], BoardSquare::Empty);
+        passant.do_move(passant.find_move(Square(7, 7), Square(7, 6), None).unwrap());
+        passant.do_move(passant.find_move(Square(4, 0), Square(4, 1), None).unwrap());
+        assert!(!passant.is_legal(en_passant));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_square() {
+        assert_eq!("e4".parse::<Square>(), Ok(Square(4, 4)));
+        assert_eq!("a8".parse::<Square>(), Ok(Square(0, 0)));
+        assert_eq!(Square(7, 7).to_string(), "h1");
+        for bad in ["", "e", "e44", "i1", "a9", "E4"] {
+            assert!(bad.parse::<Square>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_fen() {
+        for fen in [STARTING_FEN,
+                    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                    "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3"] {
+            assert_eq!(GameState::from_fen(fen).unwrap().to_fen(), fen);
+        }
+        let mut state = GameState::from_fen(STARTING_FEN).unwrap();
+        state.do_move(state.find_move(Square(4, 6), Square(4, 4), None).unwrap());
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        state.do_move(state.find_move(Square(6, 0), Square(5, 2), None).unwrap());
+        assert_eq!(state.to_fen(), "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2");
+        // Counters are optional, castling rights without a rook are dropped
+        assert_eq!(GameState::from_fen("4k3/8/8/8/8/8/8/4K2R w KQ -").unwrap().to_fen(), "4k3/8/8/8/8/8/8/4K2R w K - 0 1");
+
+        for bad in ["", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+                    "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNRR w KQkq - 0 1",
+                    "rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                    "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+                    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0"] {
+            assert!(GameState::from_fen(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        // Castling, en passant, a promotion that captures a rook and a rook move that loses castling rights
+        let mut state = GameState::from_fen("r3k2r/1P6/8/8/5p2/8/4P3/R3K2R w KQkq - 3 20").unwrap();
+        let moves = [(Square(4, 7), Square(6, 7), None), (Square(7, 0), Square(7, 3), None),
+                     (Square(4, 6), Square(4, 4), None), (Square(5, 4), Square(4, 5), None),
+                     (Square(1, 1), Square(0, 0), Some(Piece::Knight)), (Square(4, 0), Square(4, 1), None)];
+        let mut positions = vec![];
+        let mut played = vec![];
+        for (from_pos, to_pos, promotion) in moves {
+            positions.push((state.to_fen(), state.board_state));
+            let mv = state.find_move(from_pos, to_pos, promotion).unwrap();
+            state.do_move(mv);
+            played.push(mv);
+        }
+        let final_fen = state.to_fen();
+        assert_eq!(state.move_history(), played);
+        for mv in played.iter().rev() {
+            assert_eq!(state.undo(), Some(*mv));
+            let (fen, board) = positions.pop().unwrap();
+            assert_eq!(state.to_fen(), fen);
+            assert_eq!(state.board_state, board);
+        }
+        assert_eq!(state.undo(), None);
+        assert_eq!(state.position_history.len(), 1);
+        while state.redo().is_some() {}
+        assert_eq!(state.to_fen(), final_fen);
+        // A new move after taking back drops the moves that could have been replayed
+        state.undo();
+        state.do_move(state.find_move(Square(4, 0), Square(3, 0), None).unwrap());
+        assert_eq!(state.redo(), None);
+    }
+}
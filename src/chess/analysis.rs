@@ -0,0 +1,152 @@
+// Questions about a position rather than its moves: who gives check, what is pinned, which squares each
+// side controls and how much room its pieces have. Meant for highlighting threats and for evaluation.
+use super::*;
+
+// A piece that can't leave the line between its king and an enemy slider without exposing the king.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pin {
+    pub pinned: Square,
+    pub pinner: Square,
+    // The squares from the king out to the pinner, pinner included: the only ones the pinned piece may move to.
+    pub ray: Vec<Square>,
+}
+
+impl GameState {
+    // Whether the side to move's king is attacked. Never in Antichess, where the king is an ordinary piece,
+    // and not in Atomic while the two kings touch.
+    pub fn is_in_check(&self) -> bool {
+        match self.variant {
+            Variant::Antichess => false,
+            Variant::Atomic => self.bitboards.king(self.turn).is_some_and(|pos| self.is_atomic_check(pos, self.turn)),
+            _ => self.bitboards.king(self.turn).is_some_and(|pos| self.is_square_attacked(pos, !self.turn))
+        }
+    }
+
+    // The enemy pieces giving check to the side to move: none, one, or two for a double check.
+    pub fn checkers(&self) -> Vec<Square> {
+        match self.bitboards.king(self.turn) {
+            Some(king) if self.is_in_check() => self.attackers_of(king, !self.turn),
+            _ => vec![]
+        }
+    }
+
+    // Every piece of color that is absolutely pinned to its king.
+    pub fn pins(&self, color: Color) -> Vec<Pin> {
+        let king = match self.bitboards.king(color) {
+            Some(king) if self.variant != Variant::Antichess => square_index(king),
+            _ => return vec![]
+        };
+        let occupied = self.bitboards.occupied();
+        // Only a piece the king could see along a line can be pinned
+        let in_line = (bitboard::bishop_attacks(king, occupied) | bitboard::rook_attacks(king, occupied)) & self.bitboards.color(color);
+        bitboard::squares(in_line).filter_map(|pinned| {
+            let (pinner, line) = self.pin(pinned)?;
+            let mut ray: Vec<Square> = bitboard::squares(line).collect();
+            ray.sort_by_key(|pos| square_index(*pos).abs_diff(king));
+            Some(Pin { pinned, pinner, ray })
+        }).collect()
+    }
+
+    // If the piece at pos is pinned to its king, the pinning piece and the squares the pinned piece may still
+    // move to: those between the king and the pinner, and the pinner's own.
+    pub(super) fn pin(&self, pos: Square) -> Option<(Square, Bitboard)> {
+        let color = match self.board_state[pos.1][pos.0] {
+            BoardSquare::Occupied(Piece::King, _) | BoardSquare::Empty => return None,
+            BoardSquare::Occupied(_, color) => color
+        };
+        let king = square_index(self.bitboards.king(color)?);
+        let occupied = self.bitboards.occupied();
+        let enemy = !color;
+        let sliders = self.bitboards.pieces(Piece::Bishop, enemy) | self.bitboards.pieces(Piece::Rook, enemy) | self.bitboards.pieces(Piece::Queen, enemy);
+        let uncovered = self.bitboards.attackers(king, occupied & !square_bit(pos)) & !self.bitboards.attackers(king, occupied) & sliders;
+        let pinner = bitboard::squares(uncovered).next()?;
+        Some((pinner, bitboard::between(king, square_index(pinner)) | square_bit(pinner)))
+    }
+
+    // The pieces of color attacking pos, or defending it if the piece there is color's own. Pieces lined up
+    // behind another attacker aren't counted.
+    pub fn attackers_of(&self, pos: Square, color: Color) -> Vec<Square> {
+        bitboard::squares(self.bitboards.attackers(square_index(pos), self.bitboards.occupied()) & self.bitboards.color(color)).collect()
+    }
+
+    // For every square, indexed like the board by row and then file, how many of color's pieces attack it.
+    // On a square holding one of color's own pieces that is how many defend it.
+    pub fn attack_counts(&self, color: Color) -> [[u8; 8]; 8] {
+        let occupied = self.bitboards.occupied();
+        let mut counts = [[0; 8]; 8];
+        for from_pos in bitboard::squares(self.bitboards.color(color)) {
+            let sq = square_index(from_pos);
+            let attacks = match self.board_state[from_pos.1][from_pos.0] {
+                BoardSquare::Occupied(Piece::Pawn(_), _) => bitboard::PAWN_ATTACKS[bitboard::color_index(color)][sq],
+                BoardSquare::Occupied(Piece::Knight, _) => bitboard::KNIGHT_ATTACKS[sq],
+                BoardSquare::Occupied(Piece::Bishop, _) => bitboard::bishop_attacks(sq, occupied),
+                BoardSquare::Occupied(Piece::Rook, _) => bitboard::rook_attacks(sq, occupied),
+                BoardSquare::Occupied(Piece::Queen, _) => bitboard::bishop_attacks(sq, occupied) | bitboard::rook_attacks(sq, occupied),
+                BoardSquare::Occupied(Piece::King, _) => bitboard::KING_ATTACKS[sq],
+                BoardSquare::Empty => 0
+            };
+            for pos in bitboard::squares(attacks) {
+                counts[pos.1][pos.0] += 1;
+            }
+        }
+        counts
+    }
+
+    // How many legal moves color would have if it were to move.
+    pub fn mobility(&self, color: Color) -> usize {
+        if color == self.turn {
+            return self.legal_moves().len();
+        }
+        let mut scratch = self.clone();
+        scratch.turn = color;
+        // En passant belongs to the side to move
+        scratch.en_passant_square = None;
+        scratch.legal_moves().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn sq(name: &str) -> Square {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn test_checkers_and_pins() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        assert!(!start.is_in_check());
+        assert_eq!(start.checkers(), vec![]);
+        assert_eq!(start.pins(Color::White), vec![]);
+        // Double check from the knight and the rook
+        let double = GameState::from_fen("4r1k1/8/8/8/8/5n2/8/4K3 w - - 0 1").unwrap();
+        assert!(double.is_in_check());
+        let mut checkers = double.checkers();
+        checkers.sort_by_key(|pos| (pos.0, pos.1));
+        assert_eq!(checkers, vec![sq("e8"), sq("f3")]);
+        let pinned = GameState::from_fen("4k3/8/8/b7/8/8/3N4/4K3 w - - 0 1").unwrap();
+        assert_eq!(pinned.pins(Color::White), vec![Pin { pinned: sq("d2"), pinner: sq("a5"), ray: vec![sq("d2"), sq("c3"), sq("b4"), sq("a5")] }]);
+        assert_eq!(pinned.pins(Color::Black), vec![]);
+        // With two pieces in the way neither is pinned
+        let screened = GameState::from_fen("4k3/8/8/b7/8/2N5/3N4/4K3 w - - 0 1").unwrap();
+        assert_eq!(screened.pins(Color::White), vec![]);
+    }
+
+    #[test]
+    fn test_attack_counts_and_mobility() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        let white = start.attack_counts(Color::White);
+        // f3 is covered by the g1 knight and the e2 and g2 pawns, the e2 pawn defended by every piece around it
+        assert_eq!(white[sq("f3").1][sq("f3").0], 3);
+        assert_eq!(white[sq("e2").1][sq("e2").0], 4);
+        assert_eq!(white[sq("e4").1][sq("e4").0], 0);
+        assert_eq!(start.attack_counts(Color::Black)[sq("f6").1][sq("f6").0], 3);
+        let mut defenders = start.attackers_of(sq("e2"), Color::White);
+        defenders.sort_by_key(|pos| pos.0);
+        assert_eq!(defenders, vec![sq("d1"), sq("e1"), sq("f1"), sq("g1")]);
+        assert_eq!((start.mobility(Color::White), start.mobility(Color::Black)), (20, 20));
+        let open = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 b - - 0 1").unwrap();
+        assert_eq!(open.mobility(Color::White), 15);
+        assert_eq!(open.mobility(Color::Black), 5);
+    }
+}
@@ -0,0 +1,145 @@
+// Where the piece images come from: the first directory on the search path that has the file, or else the
+// set built into the binary from images/. The search path is put together from the --assets flag, the
+// CHESS_ASSETS environment variable and the config file, in that order. The font for the board's coordinates
+// is configured the same way.
+use super::board::PngDrawable;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Directories to search, separated like PATH.
+pub const ASSETS_ENV: &str = "CHESS_ASSETS";
+// Font family for the coordinates.
+pub const FONT_ENV: &str = "CHESS_FONT";
+// Overrides where the config file is looked for.
+pub const CONFIG_ENV: &str = "CHESS_CONFIG";
+
+const BUILT_IN: [(&str, &[u8]); 12] = [
+    ("blackpawn.png", include_bytes!("../../images/blackpawn.png")),
+    ("blackrook.png", include_bytes!("../../images/blackrook.png")),
+    ("blackbishop.png", include_bytes!("../../images/blackbishop.png")),
+    ("blackknight.png", include_bytes!("../../images/blackknight.png")),
+    ("blackqueen.png", include_bytes!("../../images/blackqueen.png")),
+    ("blackking.png", include_bytes!("../../images/blackking.png")),
+    ("whitepawn.png", include_bytes!("../../images/whitepawn.png")),
+    ("whiterook.png", include_bytes!("../../images/whiterook.png")),
+    ("whitebishop.png", include_bytes!("../../images/whitebishop.png")),
+    ("whiteknight.png", include_bytes!("../../images/whiteknight.png")),
+    ("whitequeen.png", include_bytes!("../../images/whitequeen.png")),
+    ("whiteking.png", include_bytes!("../../images/whiteking.png")),
+];
+
+// Why an image or the config file couldn't be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssetError {
+    // The file, or the built-in image, that was being loaded
+    pub source: String,
+    reason: String
+}
+impl AssetError {
+    pub(super) fn new(source: &str, reason: impl fmt::Display) -> Self {
+        AssetError { source: source.to_string(), reason: reason.to_string() }
+    }
+}
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Couldn't load {}: {}", self.source, self.reason)
+    }
+}
+impl std::error::Error for AssetError {}
+
+// The config file, JSON like {"asset_path": ["/usr/share/chess/pieces"], "font": "DejaVu Sans"}. Anything left
+// out is not configured.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ConfigFile {
+    asset_path: Vec<PathBuf>,
+    font: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assets {
+    // Searched in order for each image
+    pub search_path: Vec<PathBuf>,
+    // Family name of the font for the coordinates, the system's sans-serif if None or not installed
+    pub font: Option<String>,
+}
+
+impl Assets {
+    // The search path from the --assets flag's value, if given, then the environment, then the config file. The
+    // font is the first of the --font flag's value, the environment's and the config file's.
+    pub fn configure(flag: Option<&str>, font_flag: Option<&str>) -> Result<Self, AssetError> {
+        let config = match config_path() {
+            Some(path) => read_config(&path)?,
+            None => ConfigFile::default()
+        };
+        let mut search_path: Vec<PathBuf> = flag.map(|dirs| env::split_paths(dirs).collect()).unwrap_or_default();
+        if let Some(dirs) = env::var_os(ASSETS_ENV) {
+            search_path.extend(env::split_paths(&dirs));
+        }
+        search_path.extend(config.asset_path);
+        search_path.retain(|dir| !dir.as_os_str().is_empty());
+        let font = font_flag.map(str::to_string).or_else(|| env::var(FONT_ENV).ok()).or(config.font).filter(|font| !font.is_empty());
+        Ok(Assets { search_path, font })
+    }
+
+    // The piece image called name, scaled to width by height.
+    pub fn piece(&self, name: &str, width: f64, height: f64) -> Result<PngDrawable, AssetError> {
+        if let Some(path) = self.search_path.iter().map(|dir| dir.join(name)).find(|path| path.is_file()) {
+            return PngDrawable::new(&path.to_string_lossy(), width, height);
+        }
+        let (_, bytes) = BUILT_IN.iter().find(|(built_in, _)| *built_in == name)
+            .ok_or_else(|| AssetError::new(name, "not found on the asset path and not built in"))?;
+        PngDrawable::from_png(bytes, &format!("built-in {}", name), width, height)
+    }
+}
+
+// $CHESS_CONFIG, or else my-project/config.json in the user's config directory.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CONFIG_ENV) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("my-project").join("config.json"))
+}
+
+// No config file is the same as an empty one, but one that can't be read or parsed is an error.
+fn read_config(path: &Path) -> Result<ConfigFile, AssetError> {
+    let source = path.to_string_lossy();
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).map_err(|err| AssetError::new(&source, err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+        Err(err) => Err(AssetError::new(&source, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_assets() {
+        let built_in = Assets::default();
+        for (name, _) in BUILT_IN {
+            assert!(built_in.piece(name, 50.0, 50.0).is_ok(), "{}", name);
+        }
+        assert!(built_in.piece("purplepawn.png", 50.0, 50.0).is_err());
+
+        let dir = env::temp_dir().join(format!("chess-assets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("whitepawn.png"), b"not a png").unwrap();
+        std::fs::write(dir.join("config.json"), format!("{{\"asset_path\": [{:?}], \"font\": \"Serif\"}}", dir)).unwrap();
+        let config = read_config(&dir.join("config.json")).unwrap();
+        assert_eq!((config.asset_path, config.font), (vec![dir.clone()], Some("Serif".to_string())));
+        assert!(read_config(&dir.join("missing.json")).unwrap().asset_path.is_empty());
+        std::fs::write(dir.join("bad.json"), "{").unwrap();
+        assert!(read_config(&dir.join("bad.json")).is_err());
+        // A corrupt file on the path is an error rather than a fallback, while images it lacks are built in
+        let assets = Assets { search_path: vec![dir.clone()], font: None };
+        let err = assets.piece("whitepawn.png", 50.0, 50.0).err().unwrap();
+        assert_eq!(err.source, dir.join("whitepawn.png").to_string_lossy());
+        assert!(assets.piece("blackpawn.png", 50.0, 50.0).is_ok());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
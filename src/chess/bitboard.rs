@@ -0,0 +1,226 @@
+// Bitboards: one u64 per piece kind and per color with bit y * 8 + x set for every square holding
+// such a piece. They are kept in step with board_state and turn move generation and attack detection
+// into masking precomputed attack tables.
+use super::*;
+
+pub(super) type Bitboard = u64;
+
+pub(super) fn square_index(pos: Square) -> usize {
+    pos.1 * 8 + pos.0
+}
+
+pub(super) fn square_bit(pos: Square) -> Bitboard {
+    1 << square_index(pos)
+}
+
+fn index_square(i: usize) -> Square {
+    Square(i % 8, i / 8)
+}
+
+// The squares set in a bitboard, a8 first and h1 last.
+pub(super) fn squares(mut bb: Bitboard) -> impl Iterator<Item = Square> {
+    std::iter::from_fn(move || {
+        if bb == 0 {
+            return None;
+        }
+        let i = bb.trailing_zeros() as usize;
+        bb &= bb - 1;
+        Some(index_square(i))
+    })
+}
+
+pub(super) fn piece_kind(piece: Piece) -> usize {
+    match piece {
+        Piece::Pawn(_) => 0,
+        Piece::Knight => 1,
+        Piece::Bishop => 2,
+        Piece::Rook => 3,
+        Piece::Queen => 4,
+        Piece::King => 5
+    }
+}
+
+pub(super) fn color_index(color: Color) -> usize {
+    if color == Color::White {0} else {1}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct Bitboards {
+    // Indexed by piece_kind
+    pieces: [Bitboard; 6],
+    // White, black
+    colors: [Bitboard; 2],
+}
+
+impl Bitboards {
+    // Add sq at pos if it isn't there, take it away if it is.
+    pub(super) fn toggle(&mut self, pos: Square, sq: BoardSquare) {
+        if let BoardSquare::Occupied(piece, color) = sq {
+            self.pieces[piece_kind(piece)] ^= square_bit(pos);
+            self.colors[color_index(color)] ^= square_bit(pos);
+        }
+    }
+
+    pub(super) fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    pub(super) fn color(&self, color: Color) -> Bitboard {
+        self.colors[color_index(color)]
+    }
+
+    pub(super) fn pieces(&self, piece: Piece, color: Color) -> Bitboard {
+        self.pieces[piece_kind(piece)] & self.color(color)
+    }
+
+    pub(super) fn king(&self, color: Color) -> Option<Square> {
+        squares(self.pieces(Piece::King, color)).next()
+    }
+
+    // Pieces of both colors attacking square sq, with sliders blocked by the pieces in occupied.
+    pub(super) fn attackers(&self, sq: usize, occupied: Bitboard) -> Bitboard {
+        let [pawns, knights, bishops, rooks, queens, kings] = self.pieces;
+        // A white pawn attacks sq from where a black pawn on sq would attack, and the other way round
+        (PAWN_ATTACKS[1][sq] & pawns & self.colors[0])
+            | (PAWN_ATTACKS[0][sq] & pawns & self.colors[1])
+            | (KNIGHT_ATTACKS[sq] & knights)
+            | (KING_ATTACKS[sq] & kings)
+            | (bishop_attacks(sq, occupied) & (bishops | queens))
+            | (rook_attacks(sq, occupied) & (rooks | queens))
+    }
+}
+
+// For every square, the squares reached by one step along each offset.
+const fn step_table(offsets: &[(i64, i64)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (x, y) = ((sq % 8) as i64, (sq / 8) as i64);
+        let mut i = 0;
+        while i < offsets.len() {
+            let (tx, ty) = (x + offsets[i].0, y + offsets[i].1);
+            if tx >= 0 && tx < 8 && ty >= 0 && ty < 8 {
+                table[sq] |= 1 << (ty * 8 + tx);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+pub(super) static KNIGHT_ATTACKS: [Bitboard; 64] = step_table(&KNIGHT_OFFSETS);
+pub(super) static KING_ATTACKS: [Bitboard; 64] = step_table(&KING_OFFSETS);
+// Squares a pawn attacks, for white and black pawns. White pawns move towards y = 0.
+pub(super) static PAWN_ATTACKS: [[Bitboard; 64]; 2] = [step_table(&[(-1, -1), (1, -1)]), step_table(&[(-1, 1), (1, 1)])];
+
+// Rook directions then bishop directions.
+const SLIDE_DIRECTIONS: [(i64, i64); 8] = [ROOK_DIRECTIONS[0], ROOK_DIRECTIONS[1], ROOK_DIRECTIONS[2], ROOK_DIRECTIONS[3],
+                                           BISHOP_DIRECTIONS[0], BISHOP_DIRECTIONS[1], BISHOP_DIRECTIONS[2], BISHOP_DIRECTIONS[3]];
+
+// For every direction and square, the squares from there to the edge of the board, not counting the square itself.
+const fn ray_table() -> [[Bitboard; 64]; 8] {
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+    while dir < 8 {
+        let (dx, dy) = SLIDE_DIRECTIONS[dir];
+        let mut sq = 0;
+        while sq < 64 {
+            let (mut x, mut y) = ((sq % 8) as i64 + dx, (sq / 8) as i64 + dy);
+            while x >= 0 && x < 8 && y >= 0 && y < 8 {
+                table[dir][sq] |= 1 << (y * 8 + x);
+                x += dx;
+                y += dy;
+            }
+            sq += 1;
+        }
+        dir += 1;
+    }
+    table
+}
+
+static RAYS: [[Bitboard; 64]; 8] = ray_table();
+
+// Squares reached sliding along the given directions: each ray up to and including the first piece on it,
+// which is the lowest set bit on rays where the index goes up and the highest where it goes down.
+fn slide_attacks(sq: usize, occupied: Bitboard, directions: std::ops::Range<usize>) -> Bitboard {
+    let mut attacks = 0;
+    for dir in directions {
+        let ray = RAYS[dir][sq];
+        let blockers = ray & occupied;
+        if blockers == 0 {
+            attacks |= ray;
+            continue;
+        }
+        let (dx, dy) = SLIDE_DIRECTIONS[dir];
+        let first = if dy > 0 || (dy == 0 && dx > 0) {
+            blockers.trailing_zeros()
+        } else {
+            63 - blockers.leading_zeros()
+        };
+        attacks |= ray & !RAYS[dir][first as usize];
+    }
+    attacks
+}
+
+// The squares strictly between a and b if they share a rank, file or diagonal, otherwise none.
+pub(super) fn between(a: usize, b: usize) -> Bitboard {
+    for ray in RAYS.iter() {
+        if ray[a] & (1 << b) != 0 {
+            return ray[a] & !ray[b] & !(1 << b);
+        }
+    }
+    0
+}
+
+pub(super) fn rook_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    slide_attacks(sq, occupied, 0..4)
+}
+
+pub(super) fn bishop_attacks(sq: usize, occupied: Bitboard) -> Bitboard {
+    slide_attacks(sq, occupied, 4..8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_attack_tables() {
+        let sq = |name: &str| square_index(name.parse().unwrap());
+        let set = |names: &[&str]| names.iter().fold(0, |bb, name| bb | (1 << sq(name)));
+        assert_eq!(KNIGHT_ATTACKS[sq("a1")], set(&["b3", "c2"]));
+        assert_eq!(KING_ATTACKS[sq("h8")], set(&["g8", "g7", "h7"]));
+        assert_eq!(PAWN_ATTACKS[0][sq("e4")], set(&["d5", "f5"]));
+        assert_eq!(PAWN_ATTACKS[1][sq("a5")], set(&["b4"]));
+        let occupied = set(&["d6", "b4", "g4", "f2"]);
+        assert_eq!(rook_attacks(sq("d4"), occupied), set(&["d5", "d6", "e4", "f4", "g4", "c4", "b4", "d3", "d2", "d1"]));
+        assert_eq!(bishop_attacks(sq("d4"), occupied), set(&["c5", "b6", "a7", "e5", "f6", "g7", "h8", "c3", "b2", "a1", "e3", "f2"]));
+        assert_eq!(between(sq("a1"), sq("a4")), set(&["a2", "a3"]));
+        assert_eq!(between(sq("g7"), sq("c3")), set(&["f6", "e5", "d4"]));
+        assert_eq!(between(sq("e4"), sq("e5")), 0);
+        assert_eq!(between(sq("b1"), sq("c3")), 0);
+    }
+
+    #[test]
+    fn test_bitboards_follow_moves() {
+        let mut state = GameState::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let rebuilt = |state: &GameState| {
+            let mut bitboards = Bitboards::default();
+            for i in 0..64 {
+                let pos = index_square(i);
+                bitboards.toggle(pos, state.board_state[pos.1][pos.0]);
+            }
+            bitboards
+        };
+        // Castling both ways, captures, en passant and a capturing promotion
+        let start = state.bitboards;
+        for uci in ["e1g1", "b4c3", "d2c3", "h3g2", "a1b1", "g2f1q", "g1f1", "c7c5", "d5c6", "e8c8"] {
+            state.do_move(state.parse_uci(uci).unwrap());
+            assert_eq!(state.bitboards, rebuilt(&state));
+        }
+        while state.undo().is_some() {
+            assert_eq!(state.bitboards, rebuilt(&state));
+        }
+        assert_eq!(state.bitboards, start);
+    }
+}
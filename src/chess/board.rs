@@ -0,0 +1,635 @@
+// Drawing the board with cairo and the game-facing board that owns the GameState, for the gui feature.
+use super::*;
+use super::assets::{AssetError, Assets};
+use super::clock::{Clock, format_clock};
+use super::labels::Labels;
+use cairo::{ ImageSurface, Context, FontSlant, FontWeight };
+use minifb::Key;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ScreenPosition(
+    pub f64,
+    pub f64
+);
+
+pub trait Drawable {
+    fn draw(&self, dt: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {Ok(())}
+}
+
+struct EmptyDrawable {}
+impl Drawable for EmptyDrawable {}
+
+pub struct PngDrawable {
+	//pngImage: Source<'a>,
+    surface: ImageSurface,
+    data: Vec<u32>,
+    width_ratio: f64, 
+    height_ratio: f64,
+}
+
+impl PngDrawable {
+    pub fn new(path: &str, width: f64, height: f64) -> Result<Self, AssetError> {
+        let bytes = std::fs::read(path).map_err(|err| AssetError::new(path, err))?;
+        Self::from_png(&bytes, path, width, height)
+    }
+
+    // The PNG image in bytes, scaled to width by height. source names it in errors.
+    pub fn from_png(bytes: &[u8], source: &str, width: f64, height: f64) -> Result<Self, AssetError> {
+        let imsurf = ImageSurface::create_from_png(&mut &bytes[..]).map_err(|err| AssetError::new(source, err))?;
+        if imsurf.width() == 0 || imsurf.height() == 0 {
+            return Err(AssetError::new(source, "the image is empty"));
+        }
+
+        let img_height = imsurf.height() as f64;
+        let img_width = imsurf.width() as f64;
+        let width_ratio = width / img_width;
+        let height_ratio = height / img_height;
+
+        Ok(PngDrawable {surface: imsurf, data: vec![], width_ratio, height_ratio})
+    }
+}
+
+impl Drawable for PngDrawable {
+    fn draw(&self, ctx: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {
+        ctx.save()?;
+        ctx.translate(position.0, position.1);
+        ctx.scale(self.width_ratio, self.height_ratio);
+        ctx.set_source_surface(&self.surface, 0.0, 0.0)?;
+        ctx.paint()?;
+        ctx.restore()?;
+        Ok(())
+    }
+}
+
+const piece_imagepaths: [(char, &str); 12] = [
+    ('p', "blackpawn.png"),
+    ('r', "blackrook.png"),
+    ('b', "blackbishop.png"),
+    ('n', "blackknight.png"),
+    ('q', "blackqueen.png"),
+    ('k', "blackking.png"),
+    ('P', "whitepawn.png"),
+    ('R', "whiterook.png"),
+    ('B', "whitebishop.png"),
+    ('N', "whiteknight.png"),
+    ('Q', "whitequeen.png"),
+    ('K', "whiteking.png"),
+];
+
+// The hints drawn over the board, each of which can be turned off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Overlay {
+    // A dot on every empty square the selected piece can move to
+    MoveDots,
+    // A ring round every piece the selected piece can take
+    CaptureRings,
+    // The from and to squares of the move just played
+    LastMove,
+    // The king of the side to move while it is in check
+    Check,
+}
+
+// Which overlays are shown. All of them are by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overlays {
+    pub move_dots: bool,
+    pub capture_rings: bool,
+    pub last_move: bool,
+    pub check: bool,
+}
+
+impl Default for Overlays {
+    fn default() -> Self {
+        Overlays { move_dots: true, capture_rings: true, last_move: true, check: true }
+    }
+}
+
+impl Overlays {
+    pub fn toggle(&mut self, overlay: Overlay) {
+        let shown = match overlay {
+            Overlay::MoveDots => &mut self.move_dots,
+            Overlay::CaptureRings => &mut self.capture_rings,
+            Overlay::LastMove => &mut self.last_move,
+            Overlay::Check => &mut self.check,
+        };
+        *shown = !*shown;
+    }
+}
+
+// What the mouse did on the board, once the button is let go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gesture {
+    // Pressed and released without moving a piece, on this square
+    Click(Square),
+    // A piece picked up from the first square and dropped on the second, or off the board if None
+    Drop(Square, Option<Square>),
+}
+
+pub struct Board {
+	width: f64,
+    height: f64,
+	pub game_state: GameState,
+	pieces: HashMap<BoardSquare, Box<dyn Drawable>>,
+    pub highlight: Option<Square>,
+    // Square and color of a pawn that is being promoted; while set, the promotion pieces are drawn on its file.
+    pub promotion_picker: Option<(Square, Color)>,
+    // Shown in the side panel, for a game played against the clock
+    pub clock: Option<Clock>,
+    // The side whose pieces start at the bottom of the screen
+    pub orientation: Color,
+    // The hints drawn for the selected piece, the last move and check
+    pub overlays: Overlays,
+    labels: Labels,
+    // Where the mouse button went down, while it is held
+    press: Option<ScreenPosition>,
+    // The square of the piece being dragged and where the mouse is now
+    drag: Option<(Square, ScreenPosition)>,
+}
+
+impl Board {
+    // A board drawn with the piece images built into the binary.
+    pub fn new(width: f64, height: f64) -> Self {
+        Self::with_assets(width, height, &Assets::default()).expect("the built-in piece images are valid PNGs")
+    }
+
+    // A board drawn with the piece images and the coordinates' font found through assets.
+    pub fn with_assets(width: f64, height: f64, assets: &Assets) -> Result<Self, AssetError> {
+        let game_state = GameState::new();
+        let mut pieces : HashMap<BoardSquare, Box<dyn Drawable>> = HashMap::new();
+        for (c, path) in piece_imagepaths {
+            if let Some(sq) = char_to_sq(c) {
+                pieces.insert(sq, Box::new(assets.piece(path, width / 8.0, height / 8.0)?));
+            }
+        }
+        pieces.insert(BoardSquare::Empty, Box::new(EmptyDrawable{}));
+        let labels = Labels::new(assets.font.as_deref(), height / 8.0 * LABEL_SIZE);
+        Ok(Board {width, height, game_state, pieces, highlight: None, promotion_picker: None, clock: None, orientation: Color::White, overlays: Overlays::default(), labels, press: None, drag: None})
+    }
+    
+    // Turn the board round, so the other side is at the bottom.
+    pub fn flip(&mut self) {
+        self.orientation = !self.orientation;
+    }
+
+    // Turn the board round or toggle an overlay for a key pressed in its window. Whether that changed anything, so
+    // the board needs drawing again.
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        if key == FLIP_KEY {
+            self.flip();
+            return true;
+        }
+        match OVERLAY_KEYS.iter().find(|(overlay_key, _)| *overlay_key == key) {
+            Some(&(_, overlay)) => {
+                self.overlays.toggle(overlay);
+                true
+            },
+            None => false
+        }
+    }
+
+    // Where pos is drawn, as the square it would be with White at the bottom. Turning the board round is its
+    // own inverse, so this also takes a square on screen back to the board.
+    fn view_square(&self, pos: Square) -> Square {
+        if self.orientation == Color::White {pos} else {Square(7 - pos.0, 7 - pos.1)}
+    }
+
+    // The square under a point on the board, or None if the point is off the board.
+    pub fn square_at(&self, pos: ScreenPosition) -> Option<Square> {
+        if pos.0 < 0.0 || pos.1 < 0.0 {
+            return None;
+        }
+        let on_screen = Square((pos.0 * 8.0 / self.width) as usize, (pos.1 * 8.0 / self.height) as usize);
+        on_screen.is_on_board().then(|| self.view_square(on_screen))
+    }
+
+    // Follow the mouse, at pos if it is over the window, with its left button down or not. Moving far enough with
+    // the button held on one of the side to move's pieces picks it up, to be drawn under the mouse until it is let
+    // go. Returns the gesture once the button comes up.
+    pub fn pointer(&mut self, pos: Option<ScreenPosition>, down: bool) -> Option<Gesture> {
+        if !down {
+            let press = self.press.take()?;
+            let released = pos.unwrap_or(press);
+            return match self.drag.take() {
+                Some((from, _)) => Some(Gesture::Drop(from, self.square_at(released))),
+                None => self.square_at(released).map(Gesture::Click)
+            };
+        }
+        let pos = pos?;
+        let Some(press) = self.press else {
+            self.press = Some(pos);
+            return None;
+        };
+        if let Some((_, at)) = self.drag.as_mut() {
+            *at = pos;
+        } else if self.promotion_picker.is_none() && (pos.0 - press.0).hypot(pos.1 - press.1) >= DRAG_DISTANCE {
+            self.drag = self.square_at(press).filter(|&from| self.game_state.is_legal_start(from)).map(|from| (from, pos));
+        }
+        None
+    }
+
+    // Forget the mouse button being down, putting back any piece being dragged.
+    pub fn cancel_gesture(&mut self) {
+        self.press = None;
+        self.drag = None;
+    }
+
+    // The square of the piece being dragged, if one is.
+    pub fn dragged(&self) -> Option<Square> {
+        self.drag.map(|(from, _)| from)
+    }
+
+    // The piece whose moves are shown: the one being dragged, or else the one clicked on.
+    fn selected(&self) -> Option<Square> {
+        self.dragged().or(self.highlight)
+    }
+
+    pub fn setup_new_game(&mut self) -> () {
+        self.setup_fen(STARTING_FEN).unwrap();
+    }
+
+    pub fn setup_fen(&mut self, fen: &str) -> Result<(), FenError> {
+        self.setup_variant_fen(Variant::Standard, fen)
+    }
+
+    pub fn setup_variant_fen(&mut self, variant: Variant, fen: &str) -> Result<(), FenError> {
+        self.game_state = GameState::from_variant_fen(variant, fen)?;
+        Ok(())
+    }
+
+    // Sets up a position from 64 squares in the notation of sq_to_char, a8 first. The board is left alone if state is malformed.
+    pub fn setup_set_game(&mut self, state: &str, turn: Color, bcl: bool, bcr: bool, wcl: bool, wcr: bool, eps: Option<Square>) -> Result<(), ChessError> {
+        let squares = state.chars().map(|c| char_to_sq(c).ok_or_else(|| ChessError::BadPosition(format!("unknown piece '{}'", c))))
+            .collect::<Result<Vec<BoardSquare>, ChessError>>()?;
+        if squares.len() != 64 {
+            return Err(ChessError::BadPosition(format!("expected 64 squares, got {}", squares.len())));
+        }
+        for (i, sq) in squares.into_iter().enumerate() {
+            self.game_state.set_square(Square(i % 8, i / 8), sq);
+        }
+        self.game_state.turn = turn;
+        self.game_state.black_can_castle_left = bcl;
+        self.game_state.black_can_castle_right = bcr;
+        self.game_state.white_can_castle_left = wcl;
+        self.game_state.white_can_castle_right = wcr;
+        self.game_state.en_passant_square = eps;
+        self.game_state.reset_history();
+        Ok(())
+    }
+
+    pub fn is_checkmated(&mut self) -> bool {
+        self.game_state.is_checkmate(!self.game_state.turn)
+    }
+
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        self.game_state.outcome()
+    }
+
+    // The side whose time has run out, if the game has a clock and anyone's has.
+    pub fn flagged(&self) -> Option<Color> {
+        self.clock.as_ref().and_then(|clock| clock.flagged(Instant::now()))
+    }
+
+    // Squares the promotion picker occupies, running from the promotion square towards the middle of the board,
+    // one for each piece the variant allows.
+    fn promotion_picker_squares(&self) -> Option<Vec<(Square, Piece)>> {
+        let (pos, _) = self.promotion_picker?;
+        let dir: i64 = if pos.1 == 0 {1} else {-1};
+        let pieces = self.game_state.variant().promotion_pieces();
+        Some(pieces.iter().enumerate().map(|(i, piece)| (Square(pos.0, (pos.1 as i64 + dir * i as i64) as usize), *piece)).collect())
+    }
+
+    // The promotion piece shown at the clicked square, if the picker is open and the click landed on it.
+    pub fn promotion_picker_choice(&self, click: Square) -> Option<Piece> {
+        self.promotion_picker_squares()?.iter().find(|&&(pos, _)| pos == click).map(|&(_, piece)| piece)
+    }
+
+    // The strip right of the board: a box per player, the one at the bottom of the board's below the middle, lit up
+    // for the side to move and showing its clock if there is one. A variant game has its name on top, and
+    // Three-check shows the checks each side has given.
+    fn draw_side_panel(&self, ctx: &Context) -> Result<(), Box<dyn Error>> {
+        let now = Instant::now();
+        let variant = self.game_state.variant();
+        ctx.set_source_rgb(PANEL_COLOR.0, PANEL_COLOR.1, PANEL_COLOR.2);
+        ctx.rectangle(self.width, 0.0, SIDE_PANEL_WIDTH, self.height);
+        ctx.fill()?;
+        ctx.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+        if variant != Variant::Standard {
+            ctx.set_source_rgb(TO_MOVE_COLOR.0, TO_MOVE_COLOR.1, TO_MOVE_COLOR.2);
+            ctx.set_font_size(12.0);
+            ctx.move_to(self.width + 8.0, 20.0);
+            ctx.show_text(&variant.to_string())?;
+        }
+        let box_height = 64.0;
+        for (color, top) in [(!self.orientation, self.height / 2.0 - box_height - 8.0), (self.orientation, self.height / 2.0 + 8.0)] {
+            let remaining = self.clock.as_ref().map(|clock| clock.remaining(color, now));
+            let fill = if remaining.is_some_and(|left| left.is_zero()) {
+                FLAGGED_COLOR
+            } else if color == self.game_state.turn && self.outcome().is_none() {
+                TO_MOVE_COLOR
+            } else {
+                light_color
+            };
+            ctx.set_source_rgb(fill.0, fill.1, fill.2);
+            ctx.rectangle(self.width + 8.0, top, SIDE_PANEL_WIDTH - 16.0, box_height);
+            ctx.fill()?;
+            ctx.set_source_rgb(0.0, 0.0, 0.0);
+            ctx.set_font_size(14.0);
+            ctx.move_to(self.width + 16.0, top + 20.0);
+            let name = if color == Color::White {"White"} else {"Black"};
+            if variant == Variant::ThreeCheck {
+                ctx.show_text(&format!("{}  +{}", name, self.game_state.checks_given(color)))?;
+            } else {
+                ctx.show_text(name)?;
+            }
+            if let Some(left) = remaining {
+                ctx.set_font_size(26.0);
+                ctx.move_to(self.width + 16.0, top + 52.0);
+                ctx.show_text(&format_clock(left))?;
+            }
+        }
+        Ok(())
+    }
+
+    // The squares the selected piece can move to, once each even when a promotion makes several moves of it,
+    // and whether the move there takes something.
+    fn move_targets(&self) -> Vec<(Square, bool)> {
+        let Some(from) = self.selected() else { return vec![] };
+        let mut targets: Vec<(Square, bool)> = vec![];
+        for mv in self.game_state.legal_moves_from(from) {
+            if !targets.iter().any(|&(to, _)| to == mv.to) {
+                targets.push((mv.to, mv.capture));
+            }
+        }
+        targets
+    }
+
+    // The king of the side to move, if it is in check.
+    fn checked_king(&self) -> Option<Square> {
+        if self.game_state.is_in_check() {self.game_state.bitboards.king(self.game_state.turn)} else {None}
+    }
+
+    // Fill a whole square, given as it is on the board, in the current colour.
+    fn fill_square(&self, ctx: &Context, pos: Square) -> Result<(), Box<dyn Error>> {
+        let pos = self.view_square(pos);
+        let tile_w = self.width / 8.0;
+        let tile_h = self.height / 8.0;
+        ctx.rectangle(pos.0 as f64 * tile_w, pos.1 as f64 * tile_h, tile_w.ceil(), tile_h.ceil());
+        ctx.fill()?;
+        Ok(())
+    }
+
+    // The tint of the last move and the check highlight, under the pieces.
+    fn draw_square_overlays(&self, ctx: &Context) -> Result<(), Box<dyn Error>> {
+        if let Some(mv) = self.game_state.last_move().filter(|_| self.overlays.last_move) {
+            ctx.set_source_rgba(LAST_MOVE_COLOR.0, LAST_MOVE_COLOR.1, LAST_MOVE_COLOR.2, OVERLAY_ALPHA);
+            self.fill_square(ctx, mv.from)?;
+            self.fill_square(ctx, mv.to)?;
+        }
+        if let Some(king) = self.checked_king().filter(|_| self.overlays.check) {
+            ctx.set_source_rgba(CHECK_COLOR.0, CHECK_COLOR.1, CHECK_COLOR.2, OVERLAY_ALPHA);
+            self.fill_square(ctx, king)?;
+        }
+        Ok(())
+    }
+
+    // Dots on the squares the selected piece can move to and rings round the pieces it can take, over the pieces.
+    fn draw_move_hints(&self, ctx: &Context) -> Result<(), Box<dyn Error>> {
+        let tile_w = self.width / 8.0;
+        let tile_h = self.height / 8.0;
+        let size = tile_w.min(tile_h);
+        ctx.set_source_rgba(HINT_COLOR.0, HINT_COLOR.1, HINT_COLOR.2, OVERLAY_ALPHA);
+        for (to, capture) in self.move_targets() {
+            let pos = self.view_square(to);
+            let (x, y) = ((pos.0 as f64 + 0.5) * tile_w, (pos.1 as f64 + 0.5) * tile_h);
+            ctx.new_sub_path();
+            if capture && self.overlays.capture_rings {
+                ctx.set_line_width(size * RING_WIDTH);
+                ctx.arc(x, y, size * (0.5 - RING_WIDTH / 2.0), 0.0, std::f64::consts::TAU);
+                ctx.stroke()?;
+            } else if !capture && self.overlays.move_dots {
+                ctx.arc(x, y, size * DOT_RADIUS, 0.0, std::f64::consts::TAU);
+                ctx.fill()?;
+            }
+        }
+        Ok(())
+    }
+
+    // The files along the bottom edge, in the bottom right corner of each square, and the ranks up the left edge
+    // in the top left corners, each in the other square colour so it shows on either. They follow the board round.
+    fn draw_labels(&self, ctx: &Context) -> Result<(), Box<dyn Error>> {
+        let tile_w = self.width / 8.0;
+        let tile_h = self.height / 8.0;
+        let margin = tile_h * LABEL_MARGIN;
+        for i in 0..8 {
+            let file = self.view_square(Square(i, 7));
+            let rank = self.view_square(Square(0, i));
+            let labels = [
+                ((b'a' + file.0 as u8) as char, Square(i, 7), false),
+                ((b'8' - rank.1 as u8) as char, Square(0, i), true),
+            ];
+            for (c, on_screen, top_left) in labels {
+                let Some((width, height)) = self.labels.extent(c) else { continue };
+                let color = if (on_screen.0 + on_screen.1) % 2 == 0 {dark_color} else {light_color};
+                ctx.set_source_rgb(color.0, color.1, color.2);
+                let (x, y) = if top_left {
+                    (on_screen.0 as f64 * tile_w + margin, on_screen.1 as f64 * tile_h + margin + height)
+                } else {
+                    ((on_screen.0 + 1) as f64 * tile_w - margin - width, (on_screen.1 + 1) as f64 * tile_h - margin)
+                };
+                self.labels.draw(ctx, c, x, y)?;
+            }
+        }
+        Ok(())
+    }
+}
+const light_color: (f64, f64, f64) = (180.0 / 255.0, 175.0 / 255.0, 165.0 / 255.0);
+const dark_color: (f64, f64, f64) = (145.0 / 255.0, 140.0 / 255.0, 125.0 / 255.0);
+const highlight_color: (f64, f64, f64) = (180.0 / 255.0, 80.0 / 255.0, 80.0 / 255.0);
+const LAST_MOVE_COLOR: (f64, f64, f64) = (205.0 / 255.0, 210.0 / 255.0, 106.0 / 255.0);
+const CHECK_COLOR: (f64, f64, f64) = (230.0 / 255.0, 40.0 / 255.0, 40.0 / 255.0);
+const HINT_COLOR: (f64, f64, f64) = (40.0 / 255.0, 40.0 / 255.0, 40.0 / 255.0);
+const OVERLAY_ALPHA: f64 = 0.5;
+// Sizes of the move dots and capture rings as fractions of a square.
+const DOT_RADIUS: f64 = 0.15;
+const RING_WIDTH: f64 = 0.08;
+const PICKER_COLOR: (f64, f64, f64) = (235.0 / 255.0, 235.0 / 255.0, 235.0 / 255.0);
+const PANEL_COLOR: (f64, f64, f64) = (90.0 / 255.0, 85.0 / 255.0, 80.0 / 255.0);
+const TO_MOVE_COLOR: (f64, f64, f64) = (235.0 / 255.0, 235.0 / 255.0, 235.0 / 255.0);
+const FLAGGED_COLOR: (f64, f64, f64) = (220.0 / 255.0, 90.0 / 255.0, 80.0 / 255.0);
+// Height of the coordinate labels as a fraction of a square's, and their distance from its edges.
+const LABEL_SIZE: f64 = 0.2;
+const LABEL_MARGIN: f64 = 0.05;
+// Turns the board round
+const FLIP_KEY: Key = Key::F;
+// Each shows or hides one of the board's overlays
+const OVERLAY_KEYS: [(Key, Overlay); 4] = [
+    (Key::M, Overlay::MoveDots),
+    (Key::C, Overlay::CaptureRings),
+    (Key::L, Overlay::LastMove),
+    (Key::K, Overlay::Check),
+];
+// How far in pixels the mouse has to move with the button down before a click becomes a drag.
+const DRAG_DISTANCE: f64 = 4.0;
+// Width of the strip drawn to the right of the board, on top of the board's own width.
+pub const SIDE_PANEL_WIDTH: f64 = 120.0;
+impl Drawable for Board {
+    fn draw(&self, ctx: &mut Context, position: ScreenPosition) -> Result<(), Box<dyn Error>> {
+        ctx.save()?;
+        ctx.translate(position.0, position.0);
+        ctx.set_source_rgb(light_color.0, light_color.1, light_color.2);
+        ctx.rectangle(0.0, 0.0, self.width, self.height);
+        ctx.fill()?;
+        let mut first_light = true;
+        let tile_w = self.width / 8.0;
+        let tile_h = self.height / 8.0;
+        ctx.set_source_rgb(dark_color.0, dark_color.1, dark_color.2);
+        for i in 0..8 {
+            let start = if first_light {1} else {0};
+            for j in (start..8).step_by(2) {
+                ctx.rectangle(j as f64 * tile_w, i as f64 * tile_h, tile_w.ceil(), tile_h.ceil());
+            }
+            first_light = !first_light;
+        }
+        ctx.fill()?;
+        self.draw_square_overlays(ctx)?;
+        if let Some(hl_pos) = self.selected() {
+            ctx.set_source_rgb(highlight_color.0, highlight_color.1, highlight_color.2);
+            self.fill_square(ctx, hl_pos)?;
+        }
+        for i in 0..8 {
+            for j in 0..8 {
+                // The piece being dragged is drawn last, under the mouse
+                if self.dragged() == Some(Square(j, i)) {
+                    continue;
+                }
+                let bs = &self.game_state.board_state[i][j];
+                let piece_draw;
+                if let BoardSquare::Occupied(Piece::Pawn(true), color) = *bs {
+                    piece_draw = self.pieces.get(&BoardSquare::Occupied(Piece::Pawn(false), color)).unwrap();
+                } else {
+                    piece_draw = self.pieces.get(bs).unwrap();
+                }
+                let at = self.view_square(Square(j, i));
+                piece_draw.draw(ctx, ScreenPosition(at.0 as f64 * tile_w, at.1 as f64 * tile_h))?;
+            }
+        }
+        self.draw_move_hints(ctx)?;
+        self.draw_labels(ctx)?;
+        if let (Some(squares), Some((_, color))) = (self.promotion_picker_squares(), self.promotion_picker) {
+            for (pos, piece) in squares {
+                let pos = self.view_square(pos);
+                ctx.set_source_rgb(PICKER_COLOR.0, PICKER_COLOR.1, PICKER_COLOR.2);
+                ctx.rectangle(pos.0 as f64 * tile_w, pos.1 as f64 * tile_h, tile_w.ceil(), tile_h.ceil());
+                ctx.fill()?;
+                self.pieces.get(&BoardSquare::Occupied(piece, color)).unwrap().draw(ctx, ScreenPosition(pos.0 as f64 * tile_w, pos.1 as f64 * tile_h))?;
+            }
+        }
+        if let Some((from, at)) = self.drag {
+            let bs = match self.game_state.board_state[from.1][from.0] {
+                BoardSquare::Occupied(Piece::Pawn(_), color) => BoardSquare::Occupied(Piece::Pawn(false), color),
+                bs => bs
+            };
+            self.pieces.get(&bs).unwrap().draw(ctx, ScreenPosition(at.0 - tile_w / 2.0, at.1 - tile_h / 2.0))?;
+        }
+        self.draw_side_panel(ctx)?;
+        ctx.restore()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_checkmate() -> Result<(), &'static str> {
+        let mut board = Board::new(0.0, 0.0);
+        board.setup_set_game(&format!("R...k...R.......K{}", ".".repeat(64-17)), Color::Black, false, false, false, false, None).unwrap();
+        assert!(board.is_checkmated());
+        board.setup_set_game(&format!("R...k...B.......K{}", ".".repeat(64-17)), Color::Black, false, false, false, false, None).unwrap();
+        assert!(!board.is_checkmated());
+        board.setup_set_game(&format!("R...k...R.......K{}", ".".repeat(64-17)), Color::White, false, false, false, false, None).unwrap();
+        assert!(!board.is_checkmated());
+        Ok(())
+    }
+
+    #[test]
+    fn test_orientation() {
+        let mut board = Board::new(400.0, 400.0);
+        let a8: Square = "a8".parse().unwrap();
+        assert_eq!(board.square_at(ScreenPosition(10.0, 10.0)), Some(a8));
+        assert_eq!(board.square_at(ScreenPosition(390.0, 260.0)), "h3".parse().ok());
+        assert_eq!(board.square_at(ScreenPosition(401.0, 10.0)), None);
+        board.flip();
+        assert_eq!(board.orientation, Color::Black);
+        assert_eq!(board.square_at(ScreenPosition(10.0, 10.0)), "h1".parse().ok());
+        assert_eq!(board.square_at(ScreenPosition(390.0, 260.0)), "a6".parse().ok());
+        assert_eq!(board.view_square(board.view_square(a8)), a8);
+        // The coordinates are drawn the other way round too
+        let surface = ImageSurface::create(cairo::Format::ARgb32, 400 + SIDE_PANEL_WIDTH as i32, 400).unwrap();
+        let mut ctx = Context::new(&surface).unwrap();
+        board.draw(&mut ctx, ScreenPosition(0.0, 0.0)).unwrap();
+    }
+
+    #[test]
+    fn test_overlays() {
+        let mut board = Board::new(400.0, 400.0);
+        board.setup_fen("n3k3/1P6/8/8/8/8/8/4K2r w - - 0 1").unwrap();
+        assert_eq!(board.checked_king(), "e1".parse().ok());
+        board.setup_fen("n3k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.checked_king(), None);
+        assert_eq!(board.move_targets(), vec![]);
+        // The four promotions to b8 make one dot, and taking the knight a ring
+        board.highlight = "b7".parse().ok();
+        let mut targets = board.move_targets();
+        targets.sort_by_key(|(pos, _)| pos.0);
+        assert_eq!(targets, vec![("a8".parse().unwrap(), true), ("b8".parse().unwrap(), false)]);
+        board.overlays.toggle(Overlay::MoveDots);
+        assert_eq!(board.overlays, Overlays { move_dots: false, ..Overlays::default() });
+        assert!(board.handle_key(Key::L) && !board.handle_key(Key::Z));
+        assert_eq!(board.overlays, Overlays { move_dots: false, last_move: false, ..Overlays::default() });
+        assert!(board.handle_key(Key::F));
+        assert_eq!(board.orientation, Color::Black);
+        let surface = ImageSurface::create(cairo::Format::ARgb32, 400 + SIDE_PANEL_WIDTH as i32, 400).unwrap();
+        let mut ctx = Context::new(&surface).unwrap();
+        board.draw(&mut ctx, ScreenPosition(0.0, 0.0)).unwrap();
+    }
+
+    #[test]
+    fn test_gestures() {
+        let mut board = Board::new(400.0, 400.0);
+        board.setup_new_game();
+        let (e2, e4) = (ScreenPosition(225.0, 325.0), ScreenPosition(225.0, 225.0));
+        // Pressing and letting go without moving far is a click, on an empty square as well
+        assert_eq!(board.pointer(Some(e4), true), None);
+        assert_eq!(board.pointer(Some(ScreenPosition(227.0, 226.0)), true), None);
+        assert_eq!(board.dragged(), None);
+        assert_eq!(board.pointer(Some(e4), false), "e4".parse().ok().map(Gesture::Click));
+        // Moving the pawn far enough picks it up, and its moves are shown while it is carried
+        board.pointer(Some(e2), true);
+        board.pointer(Some(ScreenPosition(225.0, 280.0)), true);
+        assert_eq!(board.dragged(), "e2".parse().ok());
+        assert_eq!(board.move_targets().len(), 2);
+        board.pointer(Some(e4), true);
+        let surface = ImageSurface::create(cairo::Format::ARgb32, 400 + SIDE_PANEL_WIDTH as i32, 400).unwrap();
+        let mut ctx = Context::new(&surface).unwrap();
+        board.draw(&mut ctx, ScreenPosition(0.0, 0.0)).unwrap();
+        assert_eq!(board.pointer(Some(e4), false), Some(Gesture::Drop("e2".parse().unwrap(), "e4".parse().ok())));
+        assert_eq!(board.dragged(), None);
+        // Dropped off the board
+        board.pointer(Some(e2), true);
+        board.pointer(Some(ScreenPosition(450.0, 325.0)), true);
+        assert_eq!(board.pointer(Some(ScreenPosition(450.0, 325.0)), false), Some(Gesture::Drop("e2".parse().unwrap(), None)));
+        // Black's pieces can't be picked up on White's move, so dragging one is just a click where it ends
+        board.pointer(Some(ScreenPosition(225.0, 75.0)), true);
+        board.pointer(Some(ScreenPosition(225.0, 175.0)), true);
+        assert_eq!(board.dragged(), None);
+        assert_eq!(board.pointer(Some(ScreenPosition(225.0, 175.0)), false), "e5".parse().ok().map(Gesture::Click));
+        board.pointer(Some(e2), true);
+        board.pointer(Some(e4), true);
+        board.cancel_gesture();
+        assert_eq!((board.dragged(), board.pointer(Some(e4), false)), (None, None));
+    }
+}
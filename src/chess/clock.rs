@@ -0,0 +1,225 @@
+// Chess clocks: the time each side has left under a time control, and what a move earns back.
+use super::*;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimeControl {
+    // The whole game in base, nothing added
+    SuddenDeath { base: Duration },
+    // increment is added after every move
+    Fischer { base: Duration, increment: Duration },
+    // Up to delay of the time spent on a move is given back, so a quick move costs nothing
+    Bronstein { base: Duration, delay: Duration },
+}
+
+impl TimeControl {
+    pub fn base(&self) -> Duration {
+        match *self {
+            TimeControl::SuddenDeath { base } | TimeControl::Fischer { base, .. } | TimeControl::Bronstein { base, .. } => base
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    if duration.subsec_nanos() == 0 {duration.as_secs().to_string()} else {duration.as_secs_f64().to_string()}
+}
+
+// Seconds as in the PGN TimeControl tag: "300" for sudden death and "300+5" for an increment. A delay,
+// which PGN has no way to write, is "300d5".
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TimeControl::SuddenDeath { base } => write!(f, "{}", seconds(base)),
+            TimeControl::Fischer { base, increment } => write!(f, "{}+{}", seconds(base), seconds(increment)),
+            TimeControl::Bronstein { base, delay } => write!(f, "{}d{}", seconds(base), seconds(delay)),
+        }
+    }
+}
+
+// Why a string is not a time control.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeControlParseError {
+    text: String
+}
+impl fmt::Display for TimeControlParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid time control '{}', expected seconds like 300, 300+5 or 300d5", self.text)
+    }
+}
+impl std::error::Error for TimeControlParseError {}
+
+impl FromStr for TimeControl {
+    type Err = TimeControlParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = || TimeControlParseError { text: text.to_string() };
+        let parse = |seconds: &str| seconds.parse::<f64>().ok().filter(|s| s.is_finite() && *s >= 0.0).map(Duration::from_secs_f64).ok_or_else(error);
+        let control = if let Some((base, increment)) = text.split_once('+') {
+            TimeControl::Fischer { base: parse(base)?, increment: parse(increment)? }
+        } else if let Some((base, delay)) = text.split_once('d') {
+            TimeControl::Bronstein { base: parse(base)?, delay: parse(delay)? }
+        } else {
+            TimeControl::SuddenDeath { base: parse(text)? }
+        };
+        if control.base().is_zero() {
+            return Err(error());
+        }
+        Ok(control)
+    }
+}
+
+fn side(color: Color) -> usize {
+    if color == Color::White {0} else {1}
+}
+
+// A two-sided game clock. Times are worked out from the Instants passed in, so the clock itself never
+// needs ticking; callers pass Instant::now().
+#[derive(Clone, Debug)]
+pub struct Clock {
+    pub control: TimeControl,
+    // White then Black. For the running side, as it was when its clock was started.
+    remaining: [Duration; 2],
+    // The side whose time is going down, and since when
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    // Both sides get the base time, and neither clock is running.
+    pub fn new(control: TimeControl) -> Self {
+        Clock { control, remaining: [control.base(); 2], running: None }
+    }
+
+    // Start color's time going, stopping the other side's without any bonus.
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.stop(now);
+        self.running = Some((color, now));
+    }
+
+    // Stop whichever side is running, e.g. when the game ends.
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, _)) = self.running {
+            self.remaining[side(color)] = self.remaining(color, now);
+            self.running = None;
+        }
+    }
+
+    pub fn running(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    pub fn remaining(&self, color: Color, now: Instant) -> Duration {
+        match self.running {
+            Some((running, since)) if running == color => self.remaining[side(color)].saturating_sub(now.saturating_duration_since(since)),
+            _ => self.remaining[side(color)]
+        }
+    }
+
+    // Overwrite color's time, e.g. with the time the other end of a network game says it has. For the
+    // running side this is its time as of when its clock was started.
+    pub fn set_remaining(&mut self, color: Color, remaining: Duration) {
+        self.remaining[side(color)] = remaining;
+    }
+
+    // The side whose flag has fallen, if any: its clock is running and has reached zero.
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        self.running().filter(|&color| self.remaining(color, now).is_zero())
+    }
+
+    // The running side has moved: charge it the time used, add its bonus and start the other side.
+    // Returns the mover's time left. A side whose flag has already fallen gets nothing back.
+    pub fn press(&mut self, now: Instant) -> Duration {
+        let (mover, since) = match self.running {
+            Some(running) => running,
+            None => return Duration::ZERO
+        };
+        let used = now.saturating_duration_since(since);
+        let mut left = self.remaining[side(mover)].saturating_sub(used);
+        if !left.is_zero() {
+            left += match self.control {
+                TimeControl::SuddenDeath { .. } => Duration::ZERO,
+                TimeControl::Fischer { increment, .. } => increment,
+                TimeControl::Bronstein { delay, .. } => used.min(delay),
+            };
+        }
+        self.remaining[side(mover)] = left;
+        self.running = Some((!mover, now));
+        left
+    }
+}
+
+impl GameState {
+    // How the game ends when flagged runs out of time: a loss, unless the opponent has only a king left
+    // and so could never have won.
+    pub fn flag_fall_outcome(&self, flagged: Color) -> GameOutcome {
+        let lone_king = self.board_state.iter().flatten()
+            .all(|square| !matches!(square, BoardSquare::Occupied(piece, color) if *color != flagged && *piece != Piece::King));
+        if lone_king {GameOutcome::TimeoutVsInsufficientMaterial} else {GameOutcome::Timeout { winner: !flagged }}
+    }
+}
+
+// Clock time the way it is shown: minutes and seconds, with tenths in the last ten seconds.
+pub fn format_clock(remaining: Duration) -> String {
+    if remaining < Duration::from_secs(10) {
+        let tenths = remaining.as_millis() / 100;
+        format!("{}.{}", tenths / 10, tenths % 10)
+    } else {
+        let seconds = remaining.as_secs();
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_time_control() {
+        assert_eq!("300".parse(), Ok(TimeControl::SuddenDeath { base: Duration::from_secs(300) }));
+        assert_eq!("180+2".parse(), Ok(TimeControl::Fischer { base: Duration::from_secs(180), increment: Duration::from_secs(2) }));
+        assert_eq!("60d0.5".parse(), Ok(TimeControl::Bronstein { base: Duration::from_secs(60), delay: Duration::from_millis(500) }));
+        for text in ["300", "180+2", "60d0.5"] {
+            assert_eq!(text.parse::<TimeControl>().unwrap().to_string(), text);
+        }
+        for text in ["", "0", "-5", "5+", "x+1", "inf"] {
+            assert!(text.parse::<TimeControl>().is_err(), "{}", text);
+        }
+        assert_eq!(format_clock(Duration::from_secs(754)), "12:34");
+        assert_eq!(format_clock(Duration::from_millis(9_870)), "9.8");
+    }
+
+    #[test]
+    fn test_clock() {
+        let t0 = Instant::now();
+        let at = |seconds: u64| t0 + Duration::from_secs(seconds);
+        let mut fischer = Clock::new("60+2".parse().unwrap());
+        fischer.start(Color::White, t0);
+        assert_eq!(fischer.remaining(Color::White, at(10)), Duration::from_secs(50));
+        assert_eq!(fischer.press(at(10)), Duration::from_secs(52));
+        assert_eq!(fischer.running(), Some(Color::Black));
+        assert_eq!(fischer.remaining(Color::White, at(30)), Duration::from_secs(52));
+        assert_eq!(fischer.remaining(Color::Black, at(30)), Duration::from_secs(40));
+        assert_eq!(fischer.flagged(at(69)), None);
+        assert_eq!(fischer.flagged(at(70)), Some(Color::Black));
+        // Too late: no increment once the flag has fallen
+        assert_eq!(fischer.press(at(75)), Duration::ZERO);
+
+        let mut bronstein = Clock::new("60d5".parse().unwrap());
+        bronstein.start(Color::White, t0);
+        assert_eq!(bronstein.press(at(3)), Duration::from_secs(60));
+        assert_eq!(bronstein.press(at(13)), Duration::from_secs(55));
+        let mut sudden_death = Clock::new("60".parse().unwrap());
+        sudden_death.start(Color::White, t0);
+        assert_eq!(sudden_death.press(at(3)), Duration::from_secs(57));
+        sudden_death.set_remaining(Color::White, Duration::from_secs(40));
+        sudden_death.stop(at(10));
+        assert_eq!((sudden_death.remaining(Color::White, at(99)), sudden_death.remaining(Color::Black, at(99))), (Duration::from_secs(40), Duration::from_secs(53)));
+        assert_eq!(sudden_death.flagged(at(99)), None);
+    }
+
+    #[test]
+    fn test_flag_fall_outcome() {
+        let state = GameState::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(state.flag_fall_outcome(Color::White), GameOutcome::Timeout { winner: Color::Black });
+        let bare = GameState::from_fen("4k3/8/8/8/8/8/8/Q3K3 w - - 0 1").unwrap();
+        assert_eq!(bare.flag_fall_outcome(Color::White), GameOutcome::TimeoutVsInsufficientMaterial);
+        assert_eq!(bare.flag_fall_outcome(Color::Black), GameOutcome::Timeout { winner: Color::White });
+    }
+}
@@ -0,0 +1,568 @@
+// A computer opponent: iterative deepening alpha-beta search with a transposition table, move ordering and
+// a quiescence search over captures, scoring positions by material plus piece-square tables.
+use super::*;
+use super::polyglot::Book;
+use super::zobrist::splitmix64;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Centipawns, indexed by piece_kind. The king is never traded, so it has no material value.
+const PIECE_VALUES: [i32; 6] = [100, 320, 330, 500, 900, 0];
+const MATE: i32 = 30000;
+// Scores at least this far from zero are mates, counting down by one for each ply until mate
+const MATE_BOUND: i32 = MATE - 1000;
+const INFINITY: i32 = MATE + 1;
+const MAX_DEPTH: usize = 64;
+// Check extensions can take the search past MAX_DEPTH, but never past this
+const MAX_PLY: usize = 128;
+// Transposition table size until set_hash_size says otherwise
+pub const DEFAULT_HASH_MEGABYTES: usize = 16;
+
+// Piece-square tables from White's point of view, a8 first and h1 last like square_index.
+// Black's pieces look up the square mirrored top to bottom.
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+   -50, -40, -30, -30, -30, -30, -40, -50,
+   -40, -20,   0,   0,   0,   0, -20, -40,
+   -30,   0,  10,  15,  15,  10,   0, -30,
+   -30,   5,  15,  20,  20,  15,   5, -30,
+   -30,   0,  15,  20,  20,  15,   0, -30,
+   -30,   5,  10,  15,  15,  10,   5, -30,
+   -40, -20,   0,   5,   5,   0, -20, -40,
+   -50, -40, -30, -30, -30, -30, -40, -50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+   -20, -10, -10, -10, -10, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,  10,  10,   5,   0, -10,
+   -10,   5,   5,  10,  10,   5,   5, -10,
+   -10,   0,  10,  10,  10,  10,   0, -10,
+   -10,  10,  10,  10,  10,  10,  10, -10,
+   -10,   5,   0,   0,   0,   0,   5, -10,
+   -20, -10, -10, -10, -10, -10, -10, -20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,   5,   5,   5,   0, -10,
+    -5,   0,   5,   5,   5,   5,   0,  -5,
+     0,   0,   5,   5,   5,   5,   0,  -5,
+   -10,   5,   5,   5,   5,   5,   0, -10,
+   -10,   0,   5,   0,   0,   0,   0, -10,
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+// The king hides behind its pawns while there is enough material to attack it
+#[rustfmt::skip]
+const KING_MIDDLEGAME_TABLE: [i32; 64] = [
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -20, -30, -30, -40, -40, -30, -30, -20,
+   -10, -20, -20, -20, -20, -20, -20, -10,
+    20,  20,   0,   0,   0,   0,  20,  20,
+    20,  30,  10,   0,   0,  10,  30,  20,
+];
+// and walks to the middle once there isn't
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [i32; 64] = [
+   -50, -40, -30, -20, -20, -30, -40, -50,
+   -30, -20, -10,   0,   0, -10, -20, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  30,  40,  40,  30, -10, -30,
+   -30, -10,  20,  30,  30,  20, -10, -30,
+   -30, -30,   0,   0,   0,   0, -30, -30,
+   -50, -30, -30, -30, -30, -30, -30, -50,
+];
+// Below this much knight, bishop, rook and queen material on the board, the king uses its endgame table
+const ENDGAME_MATERIAL: i32 = 2 * (PIECE_VALUES[3] + PIECE_VALUES[2]);
+
+// The pieces in piece_kind order.
+const PIECES: [Piece; 6] = [Piece::Pawn(false), Piece::Knight, Piece::Bishop, Piece::Rook, Piece::Queen, Piece::King];
+
+// Static score of the position in centipawns, from the side to move's point of view.
+pub fn evaluate(state: &GameState) -> i32 {
+    let mut officers = 0;
+    for piece in &PIECES[1..5] {
+        officers += (state.bitboards.pieces(*piece, Color::White) | state.bitboards.pieces(*piece, Color::Black)).count_ones() as i32
+            * PIECE_VALUES[bitboard::piece_kind(*piece)];
+    }
+    let king_table = if officers < ENDGAME_MATERIAL {&KING_ENDGAME_TABLE} else {&KING_MIDDLEGAME_TABLE};
+    let mut score = 0;
+    for color in [Color::White, Color::Black] {
+        let sign = if color == state.turn {1} else {-1};
+        for piece in PIECES {
+            let kind = bitboard::piece_kind(piece);
+            let table = match piece {
+                Piece::Pawn(_) => &PAWN_TABLE,
+                Piece::Knight => &KNIGHT_TABLE,
+                Piece::Bishop => &BISHOP_TABLE,
+                Piece::Rook => &ROOK_TABLE,
+                Piece::Queen => &QUEEN_TABLE,
+                Piece::King => king_table
+            };
+            for pos in bitboard::squares(state.bitboards.pieces(piece, color)) {
+                let sq = if color == Color::White {square_index(pos)} else {square_index(pos) ^ 56};
+                score += sign * (PIECE_VALUES[kind] + table[sq]);
+            }
+        }
+    }
+    score
+}
+
+// When to stop searching. Any limit left as None doesn't apply; with none at all the search runs to MAX_DEPTH.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    // None only when the side to move has no legal moves
+    pub best_move: Option<Move>,
+    // Centipawns for the side to move, or a mate score; see mate_in
+    pub score: i32,
+    // The deepest iteration that finished
+    pub depth: usize,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    // Moves until mate if the score is a forced mate: positive when the side to move mates, negative when it gets mated.
+    pub fn mate_in(&self) -> Option<i32> {
+        if self.score >= MATE_BOUND {
+            Some((MATE - self.score + 1) / 2)
+        } else if self.score <= -MATE_BOUND {
+            Some(-(MATE + self.score) / 2)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Bound {
+    Exact,
+    // The score is at least this much, the search having stopped at a move good enough for a cutoff
+    Lower,
+    // The score is at most this much, no move having reached alpha
+    Upper
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TableEntry {
+    hash: u64,
+    depth: usize,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+// Mate scores in the table count plies from the position stored rather than from the root.
+fn score_to_table(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {score + ply as i32} else if score <= -MATE_BOUND {score - ply as i32} else {score}
+}
+
+fn score_from_table(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {score - ply as i32} else if score <= -MATE_BOUND {score + ply as i32} else {score}
+}
+
+pub struct Engine {
+    // Indexed by Zobrist hash, kept between searches
+    table: Vec<Option<TableEntry>>,
+    // Two quiet moves per ply that caused a cutoff, tried early in sibling positions
+    killers: Vec<[Option<Move>; 2]>,
+    nodes: u64,
+    limits: SearchLimits,
+    start: Instant,
+    stopped: bool,
+    // Set from another thread to end the search early. The engine never clears it.
+    stop: Arc<AtomicBool>,
+    root_best: Option<Move>,
+    // Opening moves played straight from the book, without searching, while the position is in it
+    book: Option<Book>,
+    // State of the generator that picks between book moves
+    random: u64,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let mut engine = Engine {
+            table: vec![],
+            killers: vec![[None; 2]; MAX_PLY + 1],
+            nodes: 0,
+            limits: SearchLimits::default(),
+            start: Instant::now(),
+            stopped: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            root_best: None,
+            book: None,
+            random: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+        };
+        engine.set_hash_size(DEFAULT_HASH_MEGABYTES);
+        engine
+    }
+
+    // Resizes the transposition table to about the given number of megabytes, forgetting what it held.
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        let entries = (megabytes << 20) / std::mem::size_of::<Option<TableEntry>>();
+        self.table = vec![None; entries.max(1)];
+    }
+
+    // Storing true makes a running search return as soon as it notices, with the best move found so far.
+    // It stays set, stopping every later search straight away, until the caller stores false again.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    // Forget everything learned in earlier searches, e.g. before a new game.
+    pub fn clear(&mut self) {
+        self.table.iter_mut().for_each(|entry| *entry = None);
+    }
+
+    pub fn set_book(&mut self, book: Option<Book>) {
+        self.book = book;
+    }
+
+    // The move the engine would play, or None if there are no legal moves.
+    pub fn best_move(&mut self, state: &GameState, limits: SearchLimits) -> Option<Move> {
+        self.search(state, limits).best_move
+    }
+
+    // Searches one ply deeper at a time until a limit is reached, answering with the last iteration that finished.
+    pub fn search(&mut self, state: &GameState, limits: SearchLimits) -> SearchResult {
+        self.search_with(state, limits, |_| {})
+    }
+
+    // Like search, calling report with the result of each iteration as it finishes.
+    pub fn search_with(&mut self, state: &GameState, limits: SearchLimits, mut report: impl FnMut(&SearchResult)) -> SearchResult {
+        self.nodes = 0;
+        self.limits = limits;
+        self.start = Instant::now();
+        self.stopped = false;
+        self.killers.iter_mut().for_each(|killers| *killers = [None; 2]);
+        let mut state = state.clone();
+        let moves = state.legal_moves();
+        let mut result = SearchResult {
+            best_move: moves.first().copied(),
+            score: if moves.is_empty() && state.is_in_check() {-MATE} else {0},
+            depth: 0,
+            nodes: 0
+        };
+        if moves.is_empty() {
+            return result;
+        }
+        // Polyglot books are for standard chess, and variants starting from the same setup share its keys
+        if let Some(book) = self.book.as_ref().filter(|_| state.variant() == Variant::Standard) {
+            let (random, number) = splitmix64(self.random);
+            self.random = random;
+            if let Some(mv) = book.pick(&state, number) {
+                result.best_move = Some(mv);
+                return result;
+            }
+        }
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        for depth in 1..=max_depth {
+            self.root_best = None;
+            let score = self.alpha_beta(&mut state, depth, 0, -INFINITY, INFINITY);
+            if self.stopped {
+                break;
+            }
+            result = SearchResult { best_move: self.root_best.or(result.best_move), score, depth, nodes: self.nodes };
+            report(&result);
+            // Iterative deepening finds the quickest mate first
+            if score.abs() >= MATE_BOUND {
+                break;
+            }
+            // The next iteration takes several times longer than this one, so it would most likely be cut off
+            if let Some(time) = limits.time {
+                if self.start.elapsed() * 2 > time {
+                    break;
+                }
+            }
+        }
+        result.nodes = self.nodes;
+        result
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.stopped {
+            return true;
+        }
+        if self.stop.load(Ordering::Relaxed) {
+            self.stopped = true;
+        }
+        if self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes) {
+            self.stopped = true;
+        }
+        // Reading the clock is slow next to searching a node, so only look now and then
+        if self.nodes & 1023 == 0 && self.limits.time.is_some_and(|time| self.start.elapsed() >= time) {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    fn alpha_beta(&mut self, state: &mut GameState, depth: usize, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        if ply > 0 && is_draw(state) {
+            return 0;
+        }
+        if let Some(score) = variant_win_or_loss(state, ply) {
+            return score;
+        }
+        if ply >= MAX_PLY {
+            return evaluate(state);
+        }
+        // Look one ply further when in check, so mates and forced sequences aren't cut off at the horizon
+        let in_check = state.is_in_check();
+        let depth = if in_check {depth + 1} else {depth};
+        if depth == 0 {
+            return self.quiescence(state, ply, alpha, beta);
+        }
+        self.nodes += 1;
+
+        let slot = (state.hash % self.table.len() as u64) as usize;
+        let mut table_move = None;
+        if let Some(entry) = self.table[slot].filter(|entry| entry.hash == state.hash) {
+            table_move = entry.best_move;
+            let score = score_from_table(entry.score, ply);
+            if ply > 0 && entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        let mut moves = state.legal_moves();
+        if moves.is_empty() {
+            return if state.variant() == Variant::Antichess {
+                MATE - ply as i32
+            } else if in_check {
+                -MATE + ply as i32
+            } else {
+                0
+            };
+        }
+        self.order_moves(state, &mut moves, table_move, ply);
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for mv in moves {
+            state.make_move(mv);
+            let score = -self.alpha_beta(state, depth - 1, ply + 1, -beta, -alpha);
+            state.unmake_move();
+            if self.stopped {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+                if ply == 0 {
+                    self.root_best = best_move;
+                }
+            }
+            alpha = cmp::max(alpha, score);
+            if alpha >= beta {
+                if !mv.capture && self.killers[ply][0] != Some(mv) {
+                    self.killers[ply] = [Some(mv), self.killers[ply][0]];
+                }
+                break;
+            }
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table[slot] = Some(TableEntry { hash: state.hash, depth, score: score_to_table(best_score, ply), bound, best_move });
+        best_score
+    }
+
+    // Plays out captures and queen promotions until the position is quiet, so the static evaluation isn't
+    // taken in the middle of an exchange. The side to move may always decline to capture.
+    fn quiescence(&mut self, state: &mut GameState, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+        if let Some(score) = variant_win_or_loss(state, ply) {
+            return score;
+        }
+        let stand_pat = evaluate(state);
+        if stand_pat >= beta || ply >= MAX_PLY {
+            return stand_pat;
+        }
+        alpha = cmp::max(alpha, stand_pat);
+        let mut moves: Vec<Move> = state.legal_moves().into_iter()
+            .filter(|mv| mv.capture || mv.promotion == Some(Piece::Queen))
+            .collect();
+        self.order_moves(state, &mut moves, None, ply);
+        for mv in moves {
+            state.make_move(mv);
+            let score = -self.quiescence(state, ply + 1, -beta, -alpha);
+            state.unmake_move();
+            if self.stopped {
+                return 0;
+            }
+            if score >= beta {
+                return score;
+            }
+            alpha = cmp::max(alpha, score);
+        }
+        alpha
+    }
+
+    // Best first: the transposition table's move, then captures of the most valuable piece by the least valuable one,
+    // then promotions, then killer moves.
+    fn order_moves(&self, state: &GameState, moves: &mut [Move], table_move: Option<Move>, ply: usize) {
+        let value = |pos: Square| match state.board_state[pos.1][pos.0] {
+            BoardSquare::Occupied(piece, _) => PIECE_VALUES[bitboard::piece_kind(piece)],
+            BoardSquare::Empty => 0
+        };
+        moves.sort_by_cached_key(|&mv| {
+            let score = if Some(mv) == table_move {
+                1_000_000
+            } else if mv.capture {
+                // Pawns take en passant, where the captured square isn't the target
+                100_000 + 10 * value(mv.captured_square()) - value(mv.from)
+            } else if let Some(piece) = mv.promotion {
+                90_000 + PIECE_VALUES[bitboard::piece_kind(piece)]
+            } else if self.killers[ply].contains(&Some(mv)) {
+                80_000
+            } else {
+                0
+            };
+            -score
+        });
+    }
+}
+
+// The score of a variant game already won by either side, such as by a king reaching the centre. The evaluation
+// knows nothing more of variants, but this is enough to take a win or avoid a loss in reach of the search.
+fn variant_win_or_loss(state: &GameState, ply: usize) -> Option<i32> {
+    let winner = state.variant_outcome()?.winner()?;
+    Some(if winner == state.turn {MATE - ply as i32} else {-MATE + ply as i32})
+}
+
+// Drawn by rule no matter what is played next. A single repetition counts, as it can be repeated again.
+fn is_draw(state: &GameState) -> bool {
+    state.halfmove_clock >= 100 || state.repetition_count() >= 2 || state.is_insufficient_material()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn search(fen: &str, limits: SearchLimits) -> SearchResult {
+        Engine::new().search(&GameState::from_fen(fen).unwrap(), limits)
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(evaluate(&start), 0);
+        // Symmetric, and a queen up is clearly winning for whoever has it
+        let queen_up = GameState::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let queen_down = GameState::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+        assert!(evaluate(&queen_up) > 800);
+        assert_eq!(evaluate(&queen_down), -evaluate(&queen_up));
+    }
+
+    #[test]
+    fn test_search() {
+        let depth = |depth| SearchLimits { depth: Some(depth), ..SearchLimits::default() };
+        // Back rank mate
+        let mate = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", depth(4));
+        assert_eq!(mate.best_move.unwrap().to_string(), "a1a8");
+        assert_eq!(mate.mate_in(), Some(1));
+        // Mate in two: the rook cuts the king off, then the other one mates
+        let ladder = search("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", depth(5));
+        assert_eq!(ladder.mate_in(), Some(2));
+        // Take the hanging queen, not the defended knight
+        let hanging = search("4k3/8/2n5/3q4/8/1N6/3R4/4K3 w - - 0 1", depth(3));
+        assert_eq!(hanging.best_move.unwrap().to_string(), "d2d5");
+        // Quiescence sees the queen would be lost after taking a defended pawn
+        let poisoned = search("4k3/8/3p4/4p3/8/8/8/4QK2 w - - 0 1", depth(1));
+        assert_ne!(poisoned.best_move.unwrap().to_string(), "e1e5");
+        // Nothing to play
+        let mated = search("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3", depth(3));
+        assert_eq!(mated.best_move, None);
+        assert_eq!(mated.mate_in(), Some(0));
+    }
+
+    #[test]
+    fn test_limits() {
+        let state = GameState::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut engine = Engine::new();
+        let by_nodes = engine.search(&state, SearchLimits { nodes: Some(2000), ..SearchLimits::default() });
+        assert!(by_nodes.nodes <= 2000);
+        assert!(state.is_legal(by_nodes.best_move.unwrap()));
+        let start = Instant::now();
+        let by_time = engine.search(&state, SearchLimits { time: Some(Duration::from_millis(100)), ..SearchLimits::default() });
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(state.is_legal(by_time.best_move.unwrap()));
+        let by_depth = engine.best_move(&state, SearchLimits { depth: Some(2), ..SearchLimits::default() });
+        assert!(state.is_legal(by_depth.unwrap()));
+        // Stopped before it starts, it still answers with a legal move
+        engine.stop_handle().store(true, Ordering::Relaxed);
+        let stopped = engine.search(&state, SearchLimits::default());
+        assert_eq!(stopped.depth, 0);
+        assert!(state.is_legal(stopped.best_move.unwrap()));
+    }
+
+    #[test]
+    fn test_book_moves() {
+        let mut builder = polyglot::BookBuilder::new(polyglot::DEFAULT_BOOK_PLIES);
+        builder.add_game(&pgn::PgnGame::from_pgn("[Result \"1-0\"]\n\n1. b3 1-0\n").unwrap());
+        let mut engine = Engine::new();
+        engine.set_book(Some(builder.build()));
+        let mut state = GameState::from_fen(STARTING_FEN).unwrap();
+        let limits = SearchLimits { depth: Some(2), ..SearchLimits::default() };
+        let from_book = engine.search(&state, limits);
+        assert_eq!((from_book.best_move.unwrap().to_string(), from_book.depth), ("b2b3".to_string(), 0));
+        // Out of the book it searches as usual
+        state.do_move(from_book.best_move.unwrap());
+        assert_eq!(engine.search(&state, limits).depth, 2);
+    }
+}
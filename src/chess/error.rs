@@ -0,0 +1,288 @@
+// Why a move can't be played or a position can't be set up. The rules report these rather than panicking,
+// so a stray click, a malformed position or a hostile network peer gets an explanation instead of a crash.
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChessError {
+    // Coordinates past the edge of the board
+    OffBoard(Square),
+    EmptySquare(Square),
+    // The piece belongs to the side that isn't to move
+    NotYourTurn { to_move: Color },
+    GameOver(GameOutcome),
+    OwnPieceOnTarget(Square),
+    // The piece doesn't move that way even on an empty board
+    CannotReach { piece: Piece, from: Square, to: Square },
+    // The piece on this square is in the way
+    PathBlocked(Square),
+    // A pawn moving diagonally with nothing to take
+    NothingToCapture(Square),
+    // Moving the piece off its line would expose the king to the piece at pinner
+    Pinned { pinner: Square },
+    KingLeftInCheck,
+    // The king would be attacked on this square
+    MovesIntoCheck(Square),
+    NoCastlingRights(CastleSide),
+    CastlingOutOfCheck,
+    // The king would pass over this attacked square
+    CastlingThroughCheck(Square),
+    // Antichess: a capture is available, so it must be played
+    CaptureRequired,
+    // Atomic: the king would blow itself up
+    KingCannotCapture,
+    PromotionRequired,
+    // A promotion piece was given for a move that isn't a promotion
+    CannotPromote,
+    InvalidPromotion(Piece),
+    // The squares make a legal move but the capture, castling or en passant flags don't fit the position
+    MoveMismatch(Move),
+    BadPosition(String),
+}
+
+fn piece_name(piece: Piece) -> &'static str {
+    match piece {
+        Piece::Pawn(_) => "pawn",
+        Piece::Knight => "knight",
+        Piece::Bishop => "bishop",
+        Piece::Rook => "rook",
+        Piece::Queen => "queen",
+        Piece::King => "king"
+    }
+}
+
+impl fmt::Display for ChessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChessError::OffBoard(pos) => write!(f, "Square ({}, {}) is off the board", pos.0, pos.1),
+            ChessError::EmptySquare(pos) => write!(f, "There is no piece on {}", pos),
+            ChessError::NotYourTurn { to_move } => write!(f, "It is {:?}'s turn", to_move),
+            ChessError::GameOver(outcome) => write!(f, "The game is over: {}", outcome),
+            ChessError::OwnPieceOnTarget(pos) => write!(f, "{} holds a piece of the same color", pos),
+            ChessError::CannotReach { piece, from, to } => write!(f, "A {} can't move from {} to {}", piece_name(*piece), from, to),
+            ChessError::PathBlocked(pos) => write!(f, "The piece on {} is in the way", pos),
+            ChessError::NothingToCapture(pos) => write!(f, "Pawns only move diagonally to capture, and {} is empty", pos),
+            ChessError::Pinned { pinner } => write!(f, "The piece is pinned to its king by the piece on {}", pinner),
+            ChessError::KingLeftInCheck => write!(f, "The move leaves the king in check"),
+            ChessError::MovesIntoCheck(pos) => write!(f, "The king would be in check on {}", pos),
+            ChessError::NoCastlingRights(side) => write!(f, "Castling {} is no longer allowed", if *side == CastleSide::KingSide {"kingside"} else {"queenside"}),
+            ChessError::CastlingOutOfCheck => write!(f, "The king can't castle out of check"),
+            ChessError::CastlingThroughCheck(pos) => write!(f, "The king can't castle through check on {}", pos),
+            ChessError::CaptureRequired => write!(f, "A capture is available and must be played"),
+            ChessError::KingCannotCapture => write!(f, "The king can't capture in Atomic chess"),
+            ChessError::PromotionRequired => write!(f, "A pawn reaching the last rank must promote"),
+            ChessError::CannotPromote => write!(f, "Only a pawn reaching the last rank promotes"),
+            ChessError::InvalidPromotion(piece) => write!(f, "A pawn can't promote to a {}", piece_name(*piece)),
+            ChessError::MoveMismatch(mv) => write!(f, "{} does not fit the position", mv),
+            ChessError::BadPosition(reason) => write!(f, "Invalid position: {}", reason),
+        }
+    }
+}
+impl std::error::Error for ChessError {}
+
+// The square in bb closest to pos, for the first of several pieces standing in the way.
+fn nearest(pos: Square, bb: Bitboard) -> Option<Square> {
+    bitboard::squares(bb).min_by_key(|other| cmp::max(other.0.abs_diff(pos.0), other.1.abs_diff(pos.1)))
+}
+
+impl GameState {
+    // The legal move from from_pos to to_pos, as find_move gives, or the reason there isn't one.
+    pub fn try_find_move(&self, from_pos: Square, to_pos: Square, promotion: Option<Piece>) -> Result<Move, ChessError> {
+        for pos in [from_pos, to_pos] {
+            if !pos.is_on_board() {
+                return Err(ChessError::OffBoard(pos));
+            }
+        }
+        if let Some(outcome) = self.outcome() {
+            return Err(ChessError::GameOver(outcome));
+        }
+        let piece = match self.board_state[from_pos.1][from_pos.0] {
+            BoardSquare::Empty => return Err(ChessError::EmptySquare(from_pos)),
+            BoardSquare::Occupied(_, color) if color != self.turn => return Err(ChessError::NotYourTurn { to_move: self.turn }),
+            BoardSquare::Occupied(piece, _) => piece
+        };
+        let candidates: Vec<Move> = self.legal_moves_from(from_pos).into_iter().filter(|mv| mv.to == to_pos).collect();
+        if let Some(first) = candidates.first() {
+            return match (promotion, first.promotion.is_some()) {
+                (None, true) => Err(ChessError::PromotionRequired),
+                (None, false) => Ok(*first),
+                (Some(_), false) => Err(ChessError::CannotPromote),
+                (Some(piece), true) => candidates.iter().copied().find(|mv| mv.promotion == promotion).ok_or(ChessError::InvalidPromotion(piece))
+            };
+        }
+        if let Some(side) = self.castling_attempt(piece, from_pos, to_pos) {
+            return Err(self.castling_error(from_pos, side));
+        }
+        if self.bitboards.color(self.turn) & square_bit(to_pos) != 0 {
+            return Err(ChessError::OwnPieceOnTarget(to_pos));
+        }
+        if piece == Piece::King && self.variant == Variant::Atomic && self.bitboards.color(!self.turn) & square_bit(to_pos) != 0 {
+            return Err(ChessError::KingCannotCapture);
+        }
+        if self.pseudo_targets(from_pos, piece) & square_bit(to_pos) == 0 {
+            return Err(self.unreachable_error(piece, from_pos, to_pos));
+        }
+        // The piece gets there, so the trouble is a capture it ought to make or its own king
+        if self.variant == Variant::Antichess {
+            return Err(ChessError::CaptureRequired);
+        }
+        if piece == Piece::King {
+            return Err(ChessError::MovesIntoCheck(to_pos));
+        }
+        if self.variant == Variant::Atomic {
+            return Err(ChessError::KingLeftInCheck);
+        }
+        match self.pin(from_pos) {
+            Some((pinner, line)) if line & square_bit(to_pos) == 0 => Err(ChessError::Pinned { pinner }),
+            _ => Err(ChessError::KingLeftInCheck)
+        }
+    }
+
+    // Ok if mv is legal in this position, flags included, otherwise the reason it isn't.
+    pub fn check_move(&self, mv: Move) -> Result<(), ChessError> {
+        if self.try_find_move(mv.from, mv.to, mv.promotion)? != mv {
+            return Err(ChessError::MoveMismatch(mv));
+        }
+        Ok(())
+    }
+
+    // Plays mv if it is legal and the game isn't over, otherwise leaves the position alone and says why not.
+    pub fn try_move(&mut self, mv: Move) -> Result<(), ChessError> {
+        self.check_move(mv)?;
+        self.do_move(mv);
+        Ok(())
+    }
+
+    // The castling a king move from from_pos to to_pos would be: two files along the back rank from the e-file,
+    // or in Chess960 onto one of its own rooks.
+    fn castling_attempt(&self, piece: Piece, from_pos: Square, to_pos: Square) -> Option<CastleSide> {
+        let rank = back_rank(self.turn);
+        if piece != Piece::King || from_pos.1 != rank || to_pos.1 != rank || from_pos == to_pos {
+            return None;
+        }
+        let attempt = if self.variant == Variant::Chess960 {
+            self.board_state[to_pos.1][to_pos.0] == BoardSquare::Occupied(Piece::Rook, self.turn)
+        } else {
+            from_pos.0 == 4 && to_pos.0.abs_diff(4) == 2
+        };
+        attempt.then_some(if to_pos.0 > from_pos.0 {CastleSide::KingSide} else {CastleSide::QueenSide})
+    }
+
+    // Why the king on from_pos can't castle on side, given that it can't.
+    fn castling_error(&self, from_pos: Square, side: CastleSide) -> ChessError {
+        let rook_pos = self.castling_rook(self.turn, side);
+        if !self.can_castle(self.turn, side) || self.board_state[rook_pos.1][rook_pos.0] != BoardSquare::Occupied(Piece::Rook, self.turn) {
+            return ChessError::NoCastlingRights(side);
+        }
+        let (must_be_empty, king_path) = self.castling_paths(from_pos, side);
+        let others = self.bitboards.occupied() & !square_bit(from_pos) & !square_bit(rook_pos);
+        if let Some(blocker) = nearest(from_pos, must_be_empty & others) {
+            return ChessError::PathBlocked(blocker);
+        }
+        if self.is_in_check() {
+            return ChessError::CastlingOutOfCheck;
+        }
+        let king_to = castle_destinations(side, from_pos.1).0;
+        let mut crossed: Vec<Square> = bitboard::squares(king_path & !square_bit(from_pos)).collect();
+        crossed.sort_by_key(|pos| pos.0.abs_diff(from_pos.0));
+        match crossed.into_iter().find(|&pos| self.is_attacked_through(pos, others)) {
+            Some(pos) if pos != king_to => ChessError::CastlingThroughCheck(pos),
+            _ => ChessError::MovesIntoCheck(king_to)
+        }
+    }
+
+    // Why piece can't get from from_pos to to_pos, given that to_pos isn't one of its targets.
+    fn unreachable_error(&self, piece: Piece, from_pos: Square, to_pos: Square) -> ChessError {
+        let (from_sq, to_sq) = (square_index(from_pos), square_index(to_pos));
+        let occupied = self.bitboards.occupied();
+        let blockers = bitboard::between(from_sq, to_sq) & occupied;
+        let reaches_on_empty_board = match piece {
+            Piece::Pawn(has_moved) => {
+                let forward: i64 = if self.turn == Color::White {-1} else {1};
+                let dy = to_pos.1 as i64 - from_pos.1 as i64;
+                if from_pos.0.abs_diff(to_pos.0) == 1 && dy == forward {
+                    return ChessError::NothingToCapture(to_pos);
+                }
+                // Pawns don't capture straight ahead, so a piece on the target blocks too
+                if from_pos.0 == to_pos.0 && (dy == forward || (dy == 2 * forward && !has_moved)) {
+                    if let Some(blocker) = nearest(from_pos, (blockers | square_bit(to_pos)) & occupied) {
+                        return ChessError::PathBlocked(blocker);
+                    }
+                }
+                false
+            },
+            Piece::Bishop => bitboard::bishop_attacks(from_sq, 0) & square_bit(to_pos) != 0,
+            Piece::Rook => bitboard::rook_attacks(from_sq, 0) & square_bit(to_pos) != 0,
+            Piece::Queen => (bitboard::bishop_attacks(from_sq, 0) | bitboard::rook_attacks(from_sq, 0)) & square_bit(to_pos) != 0,
+            Piece::Knight | Piece::King => false
+        };
+        match nearest(from_pos, blockers) {
+            Some(blocker) if reaches_on_empty_board => ChessError::PathBlocked(blocker),
+            _ => ChessError::CannotReach { piece, from: from_pos, to: to_pos }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn sq(name: &str) -> Square {
+        name.parse().unwrap()
+    }
+    fn error(fen: &str, from: &str, to: &str, promotion: Option<Piece>) -> ChessError {
+        GameState::from_fen(fen).unwrap().try_find_move(sq(from), sq(to), promotion).unwrap_err()
+    }
+
+    #[test]
+    fn test_move_errors() {
+        assert_eq!(error(STARTING_FEN, "e4", "e5", None), ChessError::EmptySquare(sq("e4")));
+        assert_eq!(error(STARTING_FEN, "e7", "e5", None), ChessError::NotYourTurn { to_move: Color::White });
+        assert_eq!(error(STARTING_FEN, "a1", "a2", None), ChessError::OwnPieceOnTarget(sq("a2")));
+        assert_eq!(error(STARTING_FEN, "f1", "c4", None), ChessError::PathBlocked(sq("e2")));
+        assert_eq!(error(STARTING_FEN, "g1", "g3", None), ChessError::CannotReach { piece: Piece::Knight, from: sq("g1"), to: sq("g3") });
+        assert_eq!(error(STARTING_FEN, "e2", "d3", None), ChessError::NothingToCapture(sq("d3")));
+        assert_eq!(error("4k3/8/8/8/8/4p3/4P3/4K3 w - - 0 1", "e2", "e4", None), ChessError::PathBlocked(sq("e3")));
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(start.try_find_move(Square(20, 3), sq("e4"), None), Err(ChessError::OffBoard(Square(20, 3))));
+        // Pins and checks
+        assert_eq!(error("4k3/4r3/8/8/8/8/4N3/4K3 w - - 0 1", "e2", "c3", None), ChessError::Pinned { pinner: sq("e7") });
+        assert_eq!(error("4k3/4r3/8/8/8/8/4R3/4K3 w - - 0 1", "e2", "d2", None), ChessError::Pinned { pinner: sq("e7") });
+        assert_eq!(error("4k3/8/8/8/8/8/3N4/r3K3 w - - 0 1", "d2", "f3", None), ChessError::KingLeftInCheck);
+        assert_eq!(error("4k3/8/8/8/8/8/3N4/r3K3 w - - 0 1", "e1", "d1", None), ChessError::MovesIntoCheck(sq("d1")));
+        // Castling
+        assert_eq!(error("4k3/8/8/8/8/8/8/R3K2R w Q - 0 1", "e1", "g1", None), ChessError::NoCastlingRights(CastleSide::KingSide));
+        assert_eq!(error("4k3/8/8/8/8/8/8/R2QK2R w KQ - 0 1", "e1", "c1", None), ChessError::PathBlocked(sq("d1")));
+        assert_eq!(error("4k3/8/8/8/8/8/4r3/R3K2R w KQ - 0 1", "e1", "g1", None), ChessError::CastlingOutOfCheck);
+        assert_eq!(error("4k3/8/8/8/8/8/5r2/R3K2R w KQ - 0 1", "e1", "g1", None), ChessError::CastlingThroughCheck(sq("f1")));
+        assert_eq!(error("4k3/8/8/8/8/8/6r1/R3K2R w KQ - 0 1", "e1", "g1", None), ChessError::MovesIntoCheck(sq("g1")));
+        // Promotion
+        let promoting = "7k/4P3/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(error(promoting, "e7", "e8", None), ChessError::PromotionRequired);
+        assert_eq!(error(promoting, "e7", "e8", Some(Piece::King)), ChessError::InvalidPromotion(Piece::King));
+        assert_eq!(error(promoting, "a1", "a2", Some(Piece::Queen)), ChessError::CannotPromote);
+        // Variants
+        let antichess = GameState::from_variant_fen(Variant::Antichess, "8/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        assert_eq!(antichess.try_find_move(sq("a1"), sq("a2"), None), Err(ChessError::CaptureRequired));
+        let atomic = GameState::from_variant_fen(Variant::Atomic, "4k3/8/8/8/8/8/4r3/4K3 w - - 0 1").unwrap();
+        assert_eq!(atomic.try_find_move(sq("e1"), sq("e2"), None), Err(ChessError::KingCannotCapture));
+        let chess960 = GameState::from_variant_fen(Variant::Chess960, "4k3/8/8/8/8/8/8/1R2K3 w - - 0 1").unwrap();
+        assert_eq!(chess960.try_find_move(sq("e1"), sq("b1"), None), Err(ChessError::NoCastlingRights(CastleSide::QueenSide)));
+        // After the game has ended
+        let mated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        assert_eq!(error(mated, "a2", "a3", None), ChessError::GameOver(GameOutcome::Checkmate { winner: Color::Black }));
+    }
+
+    #[test]
+    fn test_try_move() {
+        let mut state = GameState::from_fen(STARTING_FEN).unwrap();
+        let e4 = state.find_move(sq("e2"), sq("e4"), None).unwrap();
+        // A move with the wrong flags, as a confused or hostile peer might send, is refused untouched
+        let forged = Move { capture: true, ..e4 };
+        assert_eq!(state.try_move(forged), Err(ChessError::MoveMismatch(forged)));
+        let off_board = Move { from: Square(200, 3), ..e4 };
+        assert_eq!(state.try_move(off_board), Err(ChessError::OffBoard(Square(200, 3))));
+        assert_eq!(state.to_fen(), STARTING_FEN);
+        assert_eq!(state.try_move(e4), Ok(()));
+        assert_eq!(state.try_move(e4), Err(ChessError::EmptySquare(sq("e2"))));
+        assert!(state.to_fen().starts_with("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b"));
+    }
+}
@@ -0,0 +1,147 @@
+// The a-h and 1-8 coordinate labels drawn along the board's edge. The glyphs are rasterised once with font-kit,
+// from the configured font or the system's sans-serif, or else drawn from a small bitmap font built in here
+// for machines without any fonts.
+use cairo::{Context, ImageSurface};
+use font_kit::canvas::{Canvas, Format, RasterizationOptions};
+use font_kit::family_name::FamilyName;
+use font_kit::font::Font;
+use font_kit::hinting::HintingOptions;
+use font_kit::properties::{Properties, Weight};
+use font_kit::source::SystemSource;
+use pathfinder_geometry::transform2d::Transform2F;
+use std::collections::HashMap;
+use std::error::Error;
+
+pub(super) const LABEL_CHARS: [char; 16] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', '1', '2', '3', '4', '5', '6', '7', '8'];
+
+// The built-in font: seven rows of five pixels each, top row first and the leftmost pixel the 0b10000 bit.
+const BITMAP_GLYPHS: [[u8; 7]; 16] = [
+    [0b00000, 0b00000, 0b01110, 0b00001, 0b01111, 0b10001, 0b01111],
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b11110],
+    [0b00000, 0b00000, 0b01110, 0b10000, 0b10000, 0b10001, 0b01110],
+    [0b00001, 0b00001, 0b01101, 0b10011, 0b10001, 0b10001, 0b01111],
+    [0b00000, 0b00000, 0b01110, 0b10001, 0b11111, 0b10000, 0b01110],
+    [0b00110, 0b01001, 0b01000, 0b11100, 0b01000, 0b01000, 0b01000],
+    [0b00000, 0b01111, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110],
+    [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001],
+    [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+    [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+    [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+    [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+    [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+    [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+    [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+    [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+];
+
+enum GlyphImage {
+    // Coverage from font-kit, used as a mask for the current colour
+    Raster(ImageSurface),
+    // A built-in bitmap, drawn a pixel-sized square at a time
+    Bitmap([u8; 7], f64),
+}
+
+struct Glyph {
+    image: GlyphImage,
+    // From the pen position on the baseline to the glyph's top left corner, so top is negative
+    left: f64,
+    top: f64,
+    width: f64,
+}
+
+pub(super) struct Labels {
+    glyphs: HashMap<char, Glyph>,
+}
+
+impl Labels {
+    // Label glyphs size pixels high, from the font family named, or the system's sans-serif if there is no such
+    // family or none was named. Any glyph that can't be rendered that way comes from the built-in font.
+    pub(super) fn new(family: Option<&str>, size: f64) -> Self {
+        let font = select_font(family);
+        let glyphs = LABEL_CHARS.iter().enumerate().map(|(i, &c)| {
+            let glyph = font.as_ref().and_then(|font| raster_glyph(font, c, size).ok().flatten()).unwrap_or_else(|| bitmap_glyph(i, size));
+            (c, glyph)
+        }).collect();
+        Labels { glyphs }
+    }
+
+    // Width and height above the baseline of the label for c, if it is one of LABEL_CHARS.
+    pub(super) fn extent(&self, c: char) -> Option<(f64, f64)> {
+        self.glyphs.get(&c).map(|glyph| (glyph.width, -glyph.top))
+    }
+
+    // Draw c in the current colour with its pen position at x on the baseline y.
+    pub(super) fn draw(&self, ctx: &Context, c: char, x: f64, y: f64) -> Result<(), Box<dyn Error>> {
+        let Some(glyph) = self.glyphs.get(&c) else { return Ok(()) };
+        match &glyph.image {
+            GlyphImage::Raster(mask) => ctx.mask_surface(mask, (x + glyph.left).round(), (y + glyph.top).round())?,
+            GlyphImage::Bitmap(rows, pixel) => {
+                for (row, bits) in rows.iter().enumerate() {
+                    for col in (0..5).filter(|col| bits & (0b10000 >> col) != 0) {
+                        ctx.rectangle(x + glyph.left + col as f64 * pixel, y + glyph.top + row as f64 * pixel, *pixel, *pixel);
+                    }
+                }
+                ctx.fill()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn select_font(family: Option<&str>) -> Option<Font> {
+    let mut families: Vec<FamilyName> = family.map(|name| FamilyName::Title(name.to_string())).into_iter().collect();
+    families.push(FamilyName::SansSerif);
+    let handle = SystemSource::new().select_best_match(&families, Properties::new().weight(Weight::BOLD)).ok()?;
+    handle.load().ok()
+}
+
+// c rasterised from font at size pixels to the em, or None if the font has no such glyph.
+fn raster_glyph(font: &Font, c: char, size: f64) -> Result<Option<Glyph>, Box<dyn Error>> {
+    let Some(glyph_id) = font.glyph_for_char(c) else { return Ok(None) };
+    let bounds = font.raster_bounds(glyph_id, size as f32, Transform2F::default(), HintingOptions::None, RasterizationOptions::GrayscaleAa)?;
+    let (width, height) = (bounds.width(), bounds.height());
+    if width <= 0 || height <= 0 {
+        return Ok(None);
+    }
+    let mut canvas = Canvas::new(bounds.size(), Format::A8);
+    let to_canvas = Transform2F::from_translation(-bounds.origin().to_f32());
+    font.rasterize_glyph(&mut canvas, glyph_id, size as f32, to_canvas, HintingOptions::None, RasterizationOptions::GrayscaleAa)?;
+    let mut mask = ImageSurface::create(cairo::Format::A8, width, height)?;
+    let stride = mask.stride() as usize;
+    {
+        let mut data = mask.data()?;
+        for row in 0..height as usize {
+            let from = &canvas.pixels[row * canvas.stride..row * canvas.stride + width as usize];
+            data[row * stride..row * stride + width as usize].copy_from_slice(from);
+        }
+    }
+    Ok(Some(Glyph { image: GlyphImage::Raster(mask), left: bounds.origin_x() as f64, top: bounds.origin_y() as f64, width: width as f64 }))
+}
+
+fn bitmap_glyph(index: usize, size: f64) -> Glyph {
+    let pixel = size / 7.0;
+    Glyph { image: GlyphImage::Bitmap(BITMAP_GLYPHS[index], pixel), left: 0.0, top: -size, width: 5.0 * pixel }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_labels() {
+        let built_in = Labels { glyphs: LABEL_CHARS.iter().enumerate().map(|(i, &c)| (c, bitmap_glyph(i, 14.0))).collect() };
+        for c in LABEL_CHARS {
+            assert_eq!(built_in.extent(c), Some((10.0, 14.0)));
+        }
+        assert_eq!(built_in.extent('x'), None);
+        // With or without a font on this machine, every label can be drawn
+        let labels = Labels::new(Some("No Such Font Family"), 14.0);
+        let surface = ImageSurface::create(cairo::Format::ARgb32, 64, 64).unwrap();
+        let ctx = Context::new(&surface).unwrap();
+        for c in LABEL_CHARS {
+            let (width, height) = labels.extent(c).unwrap();
+            assert!(width > 0.0 && height > 0.0, "{}", c);
+            labels.draw(&ctx, c, 8.0, 32.0).unwrap();
+            built_in.draw(&ctx, c, 8.0, 32.0).unwrap();
+        }
+    }
+}
@@ -0,0 +1,249 @@
+// Reading and writing moves as Standard Algebraic Notation ("Nf3", "exd5", "O-O", "e8=Q+")
+// and as the long algebraic notation used by UCI ("e2e4", "e7e8q").
+use super::*;
+
+// Why a move string could not be turned into a legal move.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveParseError {
+    notation: String,
+    reason: &'static str
+}
+impl MoveParseError {
+    fn new(notation: &str, reason: &'static str) -> Self {
+        MoveParseError { notation: notation.to_string(), reason }
+    }
+}
+impl fmt::Display for MoveParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid move '{}': {}", self.notation, self.reason)
+    }
+}
+impl std::error::Error for MoveParseError {}
+
+fn piece_letter(piece: Piece) -> char {
+    sq_to_char(BoardSquare::Occupied(piece, Color::White))
+}
+
+fn letter_piece(c: char) -> Option<Piece> {
+    match c.to_ascii_uppercase() {
+        'N' => Some(Piece::Knight),
+        'B' => Some(Piece::Bishop),
+        'R' => Some(Piece::Rook),
+        'Q' => Some(Piece::Queen),
+        'K' => Some(Piece::King),
+        _ => None
+    }
+}
+
+// Same piece type ignoring whether a pawn has moved.
+fn same_kind(a: Piece, b: Piece) -> bool {
+    matches!((a, b), (Piece::Pawn(_), Piece::Pawn(_))) || a == b
+}
+
+impl GameState {
+    fn piece_at(&self, pos: Square) -> Option<Piece> {
+        if !pos.is_on_board() {
+            return None;
+        }
+        match self.board_state[pos.1][pos.0] {
+            BoardSquare::Occupied(piece, _) => Some(piece),
+            BoardSquare::Empty => None
+        }
+    }
+
+    // SAN for a legal move in this position, including the check or mate suffix. A move that isn't legal here gets its UCI form.
+    pub fn move_to_san(&self, mv: Move) -> String {
+        let piece = match self.piece_at(mv.from) {
+            Some(piece) if self.is_legal(mv) => piece,
+            _ => return mv.to_string()
+        };
+        let mut san = String::new();
+        if let Some(side) = mv.castle {
+            san.push_str(if side == CastleSide::KingSide {"O-O"} else {"O-O-O"});
+        } else {
+            if let Piece::Pawn(_) = piece {
+                if mv.capture {
+                    san.push(mv.from.to_string().chars().next().unwrap());
+                }
+            } else {
+                san.push(piece_letter(piece));
+                // Name the file, else the rank, else both, of the moving piece if another one like it could go there too
+                let others: Vec<Square> = self.legal_moves().into_iter()
+                    .filter(|other| other.to == mv.to && other.from != mv.from && self.piece_at(other.from) == Some(piece))
+                    .map(|other| other.from)
+                    .collect();
+                if !others.is_empty() {
+                    let name = mv.from.to_string();
+                    if others.iter().all(|other| other.0 != mv.from.0) {
+                        san.push_str(&name[..1]);
+                    } else if others.iter().all(|other| other.1 != mv.from.1) {
+                        san.push_str(&name[1..]);
+                    } else {
+                        san.push_str(&name);
+                    }
+                }
+            }
+            if mv.capture {
+                san.push('x');
+            }
+            san.push_str(&mv.to.to_string());
+            if let Some(promotion) = mv.promotion {
+                san.push('=');
+                san.push(piece_letter(promotion));
+            }
+        }
+        let mut after = self.clone();
+        after.do_move(mv);
+        if after.is_in_check() {
+            san.push(if after.has_legal_move() {'+'} else {'#'});
+        }
+        san
+    }
+
+    // Find the legal move a SAN string stands for. Check and annotation suffixes are accepted but not required.
+    pub fn parse_san(&self, san: &str) -> Result<Move, MoveParseError> {
+        let err = |reason| MoveParseError::new(san, reason);
+        let body = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let castle = match body {
+            "O-O" | "0-0" => Some(CastleSide::KingSide),
+            "O-O-O" | "0-0-0" => Some(CastleSide::QueenSide),
+            _ => None
+        };
+        if castle.is_some() {
+            return self.legal_moves().into_iter().find(|mv| mv.castle == castle).ok_or_else(|| err("castling is not legal here"));
+        }
+
+        let mut chars: Vec<char> = body.chars().collect();
+        // Promotion piece, written "e8=Q" or "e8Q"
+        let mut promotion = None;
+        if let Some(&last) = chars.last() {
+            if chars.len() > 2 && last.is_ascii_uppercase() {
+                promotion = Some(letter_piece(last).filter(|p| self.variant.promotion_pieces().contains(p)).ok_or_else(|| err("bad promotion piece"))?);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+        if chars.len() < 2 {
+            return Err(err("too short"));
+        }
+        let target: String = chars[chars.len() - 2..].iter().collect();
+        let to_pos: Square = target.parse().map_err(|_| err("bad target square"))?;
+        chars.truncate(chars.len() - 2);
+
+        let piece = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                letter_piece(c).ok_or_else(|| err("unknown piece"))?
+            },
+            _ => Piece::Pawn(false)
+        };
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' => from_file = Some(c as usize - 'a' as usize),
+                '1'..='8' => from_rank = Some(8 - (c as usize - '0' as usize)),
+                'x' | '-' => {},
+                _ => return Err(err("unexpected character"))
+            }
+        }
+
+        // Promotions are told apart by the piece, everything else by where it comes from
+        let candidates: Vec<Move> = self.legal_moves().into_iter()
+            .filter(|mv| mv.to == to_pos
+                && mv.castle.is_none()
+                && self.piece_at(mv.from).is_some_and(|p| same_kind(p, piece))
+                && from_file.is_none_or(|x| mv.from.0 == x)
+                && from_rank.is_none_or(|y| mv.from.1 == y)
+                && (mv.promotion.is_none() || promotion.is_none() || mv.promotion == promotion))
+            .collect();
+        match candidates[..] {
+            [] => Err(err("no legal move matches")),
+            [mv] if mv.promotion.is_none() && promotion.is_some() => Err(err("only pawns reaching the last rank promote")),
+            [mv] => Ok(mv),
+            _ if promotion.is_none() && candidates.iter().all(|mv| mv.promotion.is_some()) => Err(err("missing promotion piece")),
+            _ => Err(err("ambiguous"))
+        }
+    }
+
+    // Find the legal move a UCI long algebraic string like "e2e4" or "e7e8q" stands for.
+    pub fn parse_uci(&self, uci: &str) -> Result<Move, MoveParseError> {
+        let err = |reason| MoveParseError::new(uci, reason);
+        if !uci.is_ascii() || (uci.len() != 4 && uci.len() != 5) {
+            return Err(err("expected 4 or 5 characters"));
+        }
+        let from_pos: Square = uci[0..2].parse().map_err(|_| err("bad from square"))?;
+        let to_pos: Square = uci[2..4].parse().map_err(|_| err("bad target square"))?;
+        let promotion = match uci[4..].chars().next() {
+            Some(c) if c.is_ascii_lowercase() => Some(letter_piece(c).filter(|p| self.variant.promotion_pieces().contains(p)).ok_or_else(|| err("bad promotion piece"))?),
+            Some(_) => return Err(err("bad promotion piece")),
+            None => None
+        };
+        let moves: Vec<Move> = self.legal_moves_from(from_pos).into_iter().filter(|mv| mv.to == to_pos).collect();
+        if moves.is_empty() {
+            return Err(err("illegal move"));
+        }
+        moves.into_iter().find(|mv| mv.promotion == promotion).ok_or_else(|| {
+            err(if promotion.is_some() {"only pawns reaching the last rank promote"} else {"missing promotion piece"})
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_san() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        let nf3 = start.find_move(Square(6, 7), Square(5, 5), None).unwrap();
+        assert_eq!(start.move_to_san(nf3), "Nf3");
+        assert_eq!(start.parse_san("Nf3"), Ok(nf3));
+        assert_eq!(start.parse_san("e4"), Ok(start.find_move(Square(4, 6), Square(4, 4), None).unwrap()));
+        assert!(start.parse_san("Nd4").is_err());
+        assert!(start.parse_san("O-O").is_err());
+
+        // Knights on b1 and f3 can both reach d2, rooks on a1 and a5 can both reach a3
+        let state = GameState::from_fen("r3k2r/8/8/R2pP3/8/5N2/8/RN2K2R w KQkq d6 0 1").unwrap();
+        for (san, from_pos, to_pos) in [("Nbd2", Square(1, 7), Square(3, 6)),
+                                        ("R1a3", Square(0, 7), Square(0, 5)),
+                                        ("exd6", Square(4, 3), Square(3, 2)),
+                                        ("O-O", Square(4, 7), Square(6, 7)),
+                                        ("Rxa8+", Square(0, 3), Square(0, 0))] {
+            let mv = state.find_move(from_pos, to_pos, None).unwrap();
+            assert_eq!(state.move_to_san(mv), san);
+            assert_eq!(state.parse_san(san), Ok(mv));
+        }
+        assert!(state.parse_san("Nd2").is_err());
+        assert!(state.parse_san("Ra3").is_err());
+        assert!(state.parse_san("O-O-O").is_err());
+
+        let promoting = GameState::from_fen("7k/4P3/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let promotion = |piece| promoting.find_move(Square(4, 1), Square(4, 0), Some(piece)).unwrap();
+        assert_eq!(promoting.move_to_san(promotion(Piece::Queen)), "e8=Q+");
+        assert_eq!(promoting.move_to_san(promotion(Piece::Knight)), "e8=N");
+        assert_eq!(promoting.parse_san("e8=R+"), Ok(promotion(Piece::Rook)));
+        assert!(promoting.parse_san("e8").is_err());
+        assert!(promoting.parse_san("Ka2=Q").is_err());
+
+        let mating = GameState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(mating.move_to_san(mating.find_move(Square(0, 7), Square(0, 0), None).unwrap()), "Ra8#");
+    }
+
+    #[test]
+    fn test_uci() {
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        let e4 = start.parse_uci("e2e4").unwrap();
+        assert_eq!((e4.from, e4.to, e4.promotion), (Square(4, 6), Square(4, 4), None));
+        assert_eq!(e4.to_string(), "e2e4");
+        assert!(start.parse_uci("e2e5").is_err());
+        assert!(start.parse_uci("e2e4q").is_err());
+        assert!(start.parse_uci("z9e4").is_err());
+        let promoting = GameState::from_fen("7k/4P3/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let underpromotion = promoting.parse_uci("e7e8n").unwrap();
+        assert_eq!(underpromotion.promotion, Some(Piece::Knight));
+        assert_eq!(underpromotion.to_string(), "e7e8n");
+        assert!(promoting.parse_uci("e7e8").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// Perft: counting the leaves of the move tree to a fixed depth. The counts for well known positions
+// are published, so any difference points at a move generation bug, and divide narrows it down to a move.
+use super::*;
+
+impl GameState {
+    // Number of distinct move sequences of the given length, with each promotion piece counted separately.
+    pub fn perft(&self, depth: usize) -> u64 {
+        self.clone().count_leaves(depth)
+    }
+
+    // Every legal move with the perft count of depth - 1 after it.
+    pub fn divide(&self, depth: usize) -> Vec<(Move, u64)> {
+        if depth == 0 {
+            return vec![];
+        }
+        let mut state = self.clone();
+        let mut counts = vec![];
+        for mv in self.legal_moves() {
+            state.make_move(mv);
+            counts.push((mv, state.count_leaves(depth - 1)));
+            state.unmake_move();
+        }
+        counts
+    }
+
+    fn count_leaves(&mut self, depth: usize) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.legal_moves();
+        // The moves are all legal, so the last ply needs only counting
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for mv in moves {
+            self.make_move(mv);
+            nodes += self.count_leaves(depth - 1);
+            self.unmake_move();
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Standard perft test positions and their published counts, at depths that keep debug builds quick.
+    const PERFT_SUITE: [(&str, usize, u64); 6] = [
+        (STARTING_FEN, 3, 8902),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2, 2039),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4, 43238),
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3, 9467),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 2, 1486),
+        ("n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1", 3, 9483),
+    ];
+
+    #[test]
+    fn test_perft() {
+        for (fen, depth, nodes) in PERFT_SUITE {
+            let state = GameState::from_fen(fen).unwrap();
+            assert_eq!(state.perft(depth), nodes, "perft({}) of {}", depth, fen);
+        }
+        let start = GameState::from_fen(STARTING_FEN).unwrap();
+        assert_eq!(start.perft(0), 1);
+        let divided = start.divide(2);
+        assert_eq!(divided.len(), 20);
+        assert!(divided.iter().all(|&(_, nodes)| nodes == 20));
+        // Promotions count once per piece
+        let promoting = GameState::from_fen("7k/4P3/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        assert_eq!(promoting.divide(1).len(), 7);
+    }
+
+    #[test]
+    fn test_rook_capture_clears_castling() {
+        let mut state = GameState::from_fen("r3k2r/8/8/8/8/8/6b1/R3K2R b KQkq - 0 1").unwrap();
+        state.do_move(state.parse_uci("g2h1").unwrap());
+        assert_eq!(state.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2b w Qkq - 0 2");
+        state.do_move(state.parse_uci("a1a8").unwrap());
+        assert_eq!(state.to_fen(), "R3k2r/8/8/8/8/8/8/4K2b b k - 0 2");
+        state.undo();
+        state.undo();
+        assert_eq!(state.to_fen(), "r3k2r/8/8/8/8/8/6b1/R3K2R b KQkq - 0 1");
+    }
+}
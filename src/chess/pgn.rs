@@ -0,0 +1,590 @@
+// Portable Game Notation: reading game collections with tags, comments, NAGs and nested variations,
+// and writing games back out in export format.
+use super::*;
+use super::notation::MoveParseError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+// Suffix annotations and the NAGs they stand for.
+const SUFFIX_NAGS: [(&str, u8); 6] = [("!", 1), ("?", 2), ("!!", 3), ("??", 4), ("!?", 5), ("?!", 6)];
+const LINE_WIDTH: usize = 80;
+// Variations inside variations deeper than this are refused rather than read with ever deeper recursion.
+const MAX_VARIATION_DEPTH: usize = 64;
+
+// Why a PGN could not be read, with the line it went wrong on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PgnError {
+    pub line: usize,
+    reason: String
+}
+impl PgnError {
+    fn new(line: usize, reason: impl Into<String>) -> Self {
+        PgnError { line, reason: reason.into() }
+    }
+}
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PGN error on line {}: {}", self.line, self.reason)
+    }
+}
+impl std::error::Error for PgnError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PgnMove {
+    pub mv: Move,
+    pub san: String,
+    pub nags: Vec<u8>,
+    // Comment written just before this move, only kept for the first move of a game or variation.
+    pub starting_comment: Option<String>,
+    pub comment: Option<String>,
+    // Alternatives to this move, each played from the position before it.
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    // Tag pairs in file order, apart from Result which is kept in result.
+    pub tags: Vec<(String, String)>,
+    pub start: GameState,
+    pub moves: Vec<PgnMove>,
+    // "1-0", "0-1", "1/2-1/2" or "*" for a game still going.
+    pub result: String,
+    // The position after that many moves of the main line, so that recording a game move by move doesn't replay
+    // it from the start each time. Played out again if moves has been changed by hand since.
+    end: (usize, GameState),
+}
+
+impl PgnGame {
+    // An empty game from start, with the Seven Tag Roster filled in with unknowns and today's date.
+    pub fn new(start: GameState) -> Self {
+        let mut tags: Vec<(String, String)> = SEVEN_TAG_ROSTER[..6].iter().map(|name| (name.to_string(), "?".to_string())).collect();
+        tags[2].1 = today();
+        let end = (0, start.clone());
+        PgnGame { tags, start, moves: vec![], result: "*".to_string(), end }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        if name == "Result" {
+            return Some(&self.result);
+        }
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        if name == "Result" {
+            self.result = value.to_string();
+        } else if let Some(tag) = self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            tag.1 = value.to_string();
+        } else {
+            self.tags.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    // The position after the main line, with its repetition history.
+    pub fn final_state(&self) -> GameState {
+        if self.end_is_current() {
+            return self.end.1.clone();
+        }
+        let mut state = self.start.clone();
+        for mv in self.moves.iter() {
+            state.do_move(mv.mv);
+        }
+        state
+    }
+
+    // Append a move to the main line, if it is legal after the moves so far.
+    pub fn push_move(&mut self, mv: Move) -> Result<(), ChessError> {
+        if !self.end_is_current() {
+            self.end = (self.moves.len(), self.final_state());
+        }
+        let state = &mut self.end.1;
+        state.check_move(mv)?;
+        let san = state.move_to_san(mv);
+        state.do_move(mv);
+        self.end.0 += 1;
+        self.moves.push(PgnMove { mv, san, nags: vec![], starting_comment: None, comment: None, variations: vec![] });
+        Ok(())
+    }
+
+    // Whether end is still the position after the main line, as far as its length and last move tell.
+    fn end_is_current(&self) -> bool {
+        self.end.0 == self.moves.len() && self.end.1.last_move() == self.moves.last().map(|mv| mv.mv)
+    }
+
+    pub fn set_outcome(&mut self, outcome: Option<GameOutcome>) {
+        self.result = match outcome.map(|outcome| outcome.winner()) {
+            Some(Some(Color::White)) => "1-0",
+            Some(Some(Color::Black)) => "0-1",
+            Some(None) => "1/2-1/2",
+            None => "*"
+        }.to_string();
+        // Export format names a loss on time, which the result alone doesn't show
+        if matches!(outcome, Some(GameOutcome::Timeout { .. } | GameOutcome::TimeoutVsInsufficientMaterial)) {
+            self.set_tag("Termination", "time forfeit");
+        }
+    }
+
+    // Read the first game of a PGN file.
+    pub fn from_pgn(pgn: &str) -> Result<Self, PgnError> {
+        parse_pgn(pgn)?.into_iter().next().ok_or_else(|| PgnError::new(1, "no game found"))
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let mut out = String::new();
+        // Seven Tag Roster first in its usual order, then everything else
+        let roster = SEVEN_TAG_ROSTER.iter().map(|&name| (name, self.tag(name).unwrap_or("?")));
+        let others = self.tags.iter().filter(|(name, _)| !SEVEN_TAG_ROSTER.contains(&name.as_str()) && !["Variant", "SetUp", "FEN"].contains(&name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        // The variant and starting position come from start, whatever the tags say
+        let variant = self.start.variant().to_string();
+        let start_fen = self.start.to_fen();
+        let mut setup = vec![];
+        if self.start.variant() != Variant::Standard {
+            setup.push(("Variant", variant.as_str()));
+        }
+        if start_fen != self.start.variant().starting_fen() {
+            setup.extend([("SetUp", "1"), ("FEN", start_fen.as_str())]);
+        }
+        for (name, value) in roster.chain(setup).chain(others) {
+            out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+        }
+        out.push('\n');
+
+        let mut tokens = vec![];
+        write_line(&self.moves, &self.start, &mut tokens);
+        tokens.push(self.result.clone());
+        let mut line_len = 0;
+        for token in tokens {
+            if line_len > 0 && line_len + 1 + token.len() > LINE_WIDTH {
+                out.push('\n');
+                line_len = 0;
+            } else if line_len > 0 {
+                out.push(' ');
+                line_len += 1;
+            }
+            out.push_str(&token);
+            line_len += token.len();
+        }
+        out.push('\n');
+        out
+    }
+}
+
+// Movetext tokens for a line of moves played from start, with variations written after the move they replace.
+fn write_line(moves: &[PgnMove], start: &GameState, tokens: &mut Vec<String>) {
+    let mut state = start.clone();
+    // Black's moves need their number repeated when something interrupted the flow before them
+    let mut needs_number = true;
+    for mv in moves {
+        if let Some(comment) = &mv.starting_comment {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        if state.turn == Color::White {
+            tokens.push(format!("{}.", state.fullmove_number));
+        } else if needs_number {
+            tokens.push(format!("{}...", state.fullmove_number));
+        }
+        tokens.push(mv.san.clone());
+        needs_number = false;
+        for nag in mv.nags.iter() {
+            tokens.push(format!("${}", nag));
+        }
+        if let Some(comment) = &mv.comment {
+            tokens.push(format!("{{{}}}", comment));
+            needs_number = true;
+        }
+        for variation in mv.variations.iter() {
+            tokens.push("(".to_string());
+            write_line(variation, &state, tokens);
+            tokens.push(")".to_string());
+            needs_number = true;
+        }
+        state.do_move(mv.mv);
+    }
+    // Glue parentheses to what they enclose
+    let mut i = 0;
+    while i + 1 < tokens.len() {
+        if tokens[i] == "(" {
+            let next = tokens.remove(i + 1);
+            tokens[i].push_str(&next);
+        } else if tokens[i + 1] == ")" && tokens[i] != "(" {
+            tokens.remove(i + 1);
+            tokens[i].push(')');
+            continue;
+        }
+        i += 1;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    San(String),
+    Open,
+    Close,
+    Result(String),
+}
+
+fn tokenize(pgn: &str) -> Result<Vec<(Token, usize)>, PgnError> {
+    let chars: Vec<char> = pgn.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+                // Escaped lines are ignored entirely
+                if chars.get(i) == Some(&'%') {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+            },
+            _ if c.is_whitespace() => i += 1,
+            '%' if i == 0 => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            },
+            '[' => {
+                i += 1;
+                let mut name = String::new();
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    name.push(chars[i]);
+                    i += 1;
+                }
+                while i < chars.len() && chars[i] == ' ' {
+                    i += 1;
+                }
+                if name.is_empty() || chars.get(i) != Some(&'"') {
+                    return Err(PgnError::new(line, "malformed tag pair"));
+                }
+                i += 1;
+                let mut value = String::new();
+                loop {
+                    match chars.get(i) {
+                        Some('"') => break,
+                        Some('\\') if matches!(chars.get(i + 1), Some('"') | Some('\\')) => {
+                            value.push(chars[i + 1]);
+                            i += 2;
+                        },
+                        Some('\n') | None => return Err(PgnError::new(line, format!("unterminated value for tag {}", name))),
+                        Some(&other) => {
+                            value.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                i += 1;
+                while i < chars.len() && chars[i] == ' ' {
+                    i += 1;
+                }
+                if chars.get(i) != Some(&']') {
+                    return Err(PgnError::new(line, format!("missing ']' after tag {}", name)));
+                }
+                i += 1;
+                tokens.push((Token::Tag(name, value), start_line));
+            },
+            '{' => {
+                i += 1;
+                let mut comment = String::new();
+                while i < chars.len() && chars[i] != '}' {
+                    if chars[i] == '\n' {
+                        line += 1;
+                    }
+                    comment.push(chars[i]);
+                    i += 1;
+                }
+                if i == chars.len() {
+                    return Err(PgnError::new(start_line, "unterminated comment"));
+                }
+                i += 1;
+                tokens.push((Token::Comment(comment.trim().to_string()), start_line));
+            },
+            ';' => {
+                let mut comment = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '\n' {
+                    comment.push(chars[i]);
+                    i += 1;
+                }
+                tokens.push((Token::Comment(comment.trim().to_string()), start_line));
+            },
+            '(' => {
+                tokens.push((Token::Open, line));
+                i += 1;
+            },
+            ')' => {
+                tokens.push((Token::Close, line));
+                i += 1;
+            },
+            '*' => {
+                tokens.push((Token::Result("*".to_string()), line));
+                i += 1;
+            },
+            '$' => {
+                i += 1;
+                let mut digits = String::new();
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    digits.push(chars[i]);
+                    i += 1;
+                }
+                let nag = digits.parse().map_err(|_| PgnError::new(line, format!("bad NAG '${}'", digits)))?;
+                tokens.push((Token::Nag(nag), line));
+            },
+            _ => {
+                let mut symbol = String::new();
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || "_+#=:-/.!?".contains(chars[i])) {
+                    symbol.push(chars[i]);
+                    i += 1;
+                }
+                if symbol.is_empty() {
+                    return Err(PgnError::new(line, format!("unexpected character '{}'", c)));
+                }
+                tokens.extend(symbol_tokens(&symbol, line)?.into_iter().map(|token| (token, line)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// A run of symbol characters: a result, a move number, a move, or a move number glued to a move.
+fn symbol_tokens(symbol: &str, line: usize) -> Result<Vec<Token>, PgnError> {
+    if ["1-0", "0-1", "1/2-1/2"].contains(&symbol) {
+        return Ok(vec![Token::Result(symbol.to_string())]);
+    }
+    let mut rest = symbol;
+    if rest.starts_with(|c: char| c.is_ascii_digit()) && rest.contains('.') {
+        let number_len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        rest = &rest[number_len..];
+    }
+    if rest.is_empty() {
+        return Ok(vec![]);
+    }
+    let san = rest.trim_end_matches(['!', '?']);
+    let suffix = &rest[san.len()..];
+    if san.is_empty() {
+        return Err(PgnError::new(line, format!("unexpected '{}'", symbol)));
+    }
+    let mut tokens = vec![Token::San(san.to_string())];
+    if !suffix.is_empty() {
+        let nag = SUFFIX_NAGS.iter().find(|&&(s, _)| s == suffix).ok_or_else(|| PgnError::new(line, format!("unknown annotation '{}'", suffix)))?;
+        tokens.push(Token::Nag(nag.1));
+    }
+    Ok(tokens)
+}
+
+// Read every game in a PGN file.
+pub fn parse_pgn(pgn: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let tokens = tokenize(pgn)?;
+    let mut games = vec![];
+    let mut pos = 0;
+    while pos < tokens.len() {
+        let mut tags = vec![];
+        while let Some((Token::Tag(name, value), _)) = tokens.get(pos) {
+            tags.push((name.clone(), value.clone()));
+            pos += 1;
+        }
+        let line = tokens.get(pos).or(tokens.last()).map_or(1, |&(_, line)| line);
+        let variant = match tags.iter().find(|(name, _)| name == "Variant") {
+            Some((_, name)) => name.parse::<Variant>().map_err(|e| PgnError::new(line, e.to_string()))?,
+            None => Variant::Standard
+        };
+        let start = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => GameState::from_variant_fen(variant, fen).map_err(|e| PgnError::new(line, e.to_string()))?,
+            None => GameState::from_variant_fen(variant, variant.starting_fen()).unwrap()
+        };
+        let mut result = tags.iter().find(|(name, _)| name == "Result").map_or("*".to_string(), |(_, value)| value.clone());
+        tags.retain(|(name, _)| name != "Result");
+        let mut moves = parse_line(&tokens, &mut pos, &start, 0)?;
+        if let Some((Token::Result(r), _)) = tokens.get(pos) {
+            result = r.clone();
+            pos += 1;
+        }
+        // Comments after the result still belong to this game, on its last move, rather than starting the next
+        while let Some((Token::Comment(comment), _)) = tokens.get(pos) {
+            if let Some(mv) = moves.last_mut() {
+                mv.comment = Some(mv.comment.take().map_or(comment.clone(), |c| format!("{} {}", c, comment)));
+            }
+            pos += 1;
+        }
+        let end = (0, start.clone());
+        games.push(PgnGame { tags, start, moves, result, end });
+    }
+    Ok(games)
+}
+
+// Moves from tokens[pos] on, played from start, up to the end of this game or variation.
+fn parse_line(tokens: &[(Token, usize)], pos: &mut usize, start: &GameState, depth: usize) -> Result<Vec<PgnMove>, PgnError> {
+    let mut moves: Vec<PgnMove> = vec![];
+    let mut state = start.clone();
+    let mut before_last = start.clone();
+    let mut pending_comment: Option<String> = None;
+    while let Some((token, line)) = tokens.get(*pos) {
+        let line = *line;
+        match token {
+            Token::San(san) => {
+                let mv = state.parse_san(san).map_err(|e: MoveParseError| {
+                    PgnError::new(line, format!("move {}{} {}", state.fullmove_number, if state.turn == Color::White {"."} else {"..."}, e))
+                })?;
+                let san = state.move_to_san(mv);
+                before_last = state.clone();
+                state.do_move(mv);
+                moves.push(PgnMove { mv, san, nags: vec![], starting_comment: pending_comment.take(), comment: None, variations: vec![] });
+            },
+            Token::Nag(nag) => match moves.last_mut() {
+                Some(mv) => mv.nags.push(*nag),
+                None => return Err(PgnError::new(line, "annotation before any move"))
+            },
+            Token::Comment(comment) => match moves.last_mut() {
+                // Several comments in a row are run together
+                Some(mv) => mv.comment = Some(mv.comment.take().map_or(comment.clone(), |c| format!("{} {}", c, comment))),
+                None => pending_comment = Some(pending_comment.take().map_or(comment.clone(), |c| format!("{} {}", c, comment)))
+            },
+            Token::Open => {
+                if moves.is_empty() {
+                    return Err(PgnError::new(line, "variation before any move"));
+                }
+                if depth >= MAX_VARIATION_DEPTH {
+                    return Err(PgnError::new(line, "variations nested too deeply"));
+                }
+                *pos += 1;
+                let variation = parse_line(tokens, pos, &before_last, depth + 1)?;
+                match tokens.get(*pos) {
+                    Some((Token::Close, _)) => {},
+                    _ => return Err(PgnError::new(line, "variation is never closed"))
+                }
+                moves.last_mut().unwrap().variations.push(variation);
+            },
+            Token::Close => {
+                if depth == 0 {
+                    return Err(PgnError::new(line, "')' without a matching '('"));
+                }
+                return Ok(moves);
+            },
+            Token::Result(_) | Token::Tag(_, _) => {
+                if depth > 0 {
+                    return Err(PgnError::new(line, "variation is never closed"));
+                }
+                return Ok(moves);
+            }
+        }
+        *pos += 1;
+    }
+    if depth > 0 {
+        let line = tokens.last().map_or(1, |&(_, line)| line);
+        return Err(PgnError::new(line, "variation is never closed"));
+    }
+    Ok(moves)
+}
+
+// Today's date in PGN's YYYY.MM.DD form.
+fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    // Days since 1970-01-01 to a civil date, from Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const GAME: &str = r#"[Event "Casual \"blitz\""]
+[Site "?"]
+[Date "2021.10.01"]
+[Round "-"]
+[White "Alice"]
+[Black "Bob"]
+[Result "1-0"]
+[ECO "C60"]
+
+{Ruy Lopez} 1. e4 e5 2. Nf3 Nc6 3. Bb5 a6!? (3... Nf6 4. O-O (4. d3 $1) 4... Nxe4) ; main line
+4. Ba4 Nf6 5. O-O Be7 1-0
+
+[Event "Second"]
+1.d4 d5 *
+"#;
+
+    #[test]
+    fn test_pgn() {
+        let games = parse_pgn(GAME).unwrap();
+        assert_eq!(games.len(), 2);
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(game.tag("ECO"), Some("C60"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.moves.len(), 10);
+        assert_eq!(game.moves[0].starting_comment.as_deref(), Some("Ruy Lopez"));
+        let a6 = &game.moves[5];
+        assert_eq!((a6.san.as_str(), a6.nags.as_slice(), a6.comment.as_deref()), ("a6", &[5][..], Some("main line")));
+        assert_eq!(a6.variations.len(), 1);
+        assert_eq!(a6.variations[0][1].san, "O-O");
+        assert_eq!(a6.variations[0][1].variations[0][0].nags, vec![1]);
+        assert_eq!(game.final_state().to_fen(), "r1bqk2r/1pppbppp/p1n2n2/4p3/B3P3/5N2/PPPP1PPP/RNBQ1RK1 w kq - 4 6");
+        assert_eq!(games[1].tag("Event"), Some("Second"));
+        assert_eq!(games[1].result, "*");
+        // A comment after the result ends the game rather than starting another
+        let commented = parse_pgn("1. e4 e5 1-0 {end}").unwrap();
+        assert_eq!(commented.len(), 1);
+        assert_eq!((commented[0].result.as_str(), commented[0].moves[1].comment.as_deref()), ("1-0", Some("end")));
+
+        // Writing out and reading back gives the same game
+        let written = game.to_pgn();
+        assert!(written.starts_with("[Event \"Casual \\\"blitz\\\"\"]\n[Site \"?\"]\n"));
+        assert!(written.replace('\n', " ").contains("3. Bb5 a6 $5 {main line} (3... Nf6 4. O-O (4. d3 $1) 4... Nxe4) 4. Ba4"));
+        let reread = PgnGame::from_pgn(&written).unwrap();
+        assert_eq!(reread.moves, game.moves);
+        assert_eq!(reread.tags, game.tags);
+
+        for (bad, line) in [("1. e4 e5 2. Ke3", 1), ("[Event \"x\"]\n\n1. e4 (1. d4", 3), ("1. e4 )", 1),
+                            ("[Event \"x]\n1. e4", 1), ("1. e4 {never closed", 1)] {
+            assert_eq!(parse_pgn(bad).unwrap_err().line, line, "{}", bad);
+        }
+        let nested = format!("1. e4 {}", "(1. d4 ".repeat(100_000));
+        assert_eq!(parse_pgn(&nested).unwrap_err(), PgnError::new(1, "variations nested too deeply"));
+    }
+
+    #[test]
+    fn test_record_game() {
+        let mut game = PgnGame::new(GameState::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap());
+        let mate = game.final_state().find_move(Square(0, 7), Square(0, 0), None).unwrap();
+        game.push_move(mate).unwrap();
+        game.set_outcome(game.final_state().outcome());
+        let written = game.to_pgn();
+        assert!(written.contains("[Result \"1-0\"]\n[SetUp \"1\"]\n[FEN \"7k/8/6K1/8/8/8/8/R7 w - - 0 1\"]\n"));
+        assert!(written.ends_with("\n1. Ra8# 1-0\n"));
+        // Moves changed by hand are played out again before the next one is checked
+        game.moves.pop();
+        game.push_move(game.final_state().find_move(Square(0, 7), Square(0, 1), None).unwrap()).unwrap();
+        assert_eq!(game.final_state().to_fen(), "7k/R7/6K1/8/8/8/8/8 b - - 1 1");
+        assert!(game.push_move(mate).is_err());
+
+        // A variant game names its variant, and reads back under the same rules
+        let mut game = PgnGame::new(GameState::from_variant_fen(Variant::Chess960, &variant::chess960_fen(0)).unwrap());
+        game.push_move(game.final_state().parse_uci("d1c3").unwrap()).unwrap();
+        let written = game.to_pgn();
+        assert!(written.contains("[Variant \"Chess960\"]\n[SetUp \"1\"]\n[FEN \"bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1\"]\n"));
+        let reread = PgnGame::from_pgn(&written).unwrap();
+        assert_eq!(reread.start.variant(), Variant::Chess960);
+        assert_eq!(reread.moves, game.moves);
+        let mut reread = reread;
+        reread.push_move(reread.final_state().parse_uci("d8c6").unwrap()).unwrap();
+        assert_eq!(reread.final_state().fullmove_number, 2);
+        assert!(PgnGame::from_pgn("[Variant \"Crazyhouse\"]\n\n1. e4 *").is_err());
+    }
+}
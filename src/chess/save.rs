@@ -0,0 +1,235 @@
+// Saved games: a game in progress written to disk as JSON, to be picked up again later in either mode. The file
+// is versioned so that an old save can still be recognised once the format moves on.
+use super::*;
+use super::clock::{Clock, TimeControl};
+use super::pgn::PgnGame;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error as _;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Written into every save. Files with a later version are refused rather than misread.
+pub const SAVE_VERSION: u32 = 1;
+
+// Why a saved game couldn't be written or read back.
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    // Not JSON, or not the shape of a saved game
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "Couldn't access the saved game: {}", err),
+            SaveError::Format(err) => write!(f, "Invalid saved game: {}", err),
+            SaveError::UnsupportedVersion(version) => write!(f, "Saved game version {} is newer than this program understands ({})", version, SAVE_VERSION),
+        }
+    }
+}
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+impl From<serde_json::Error> for SaveError {
+    fn from(err: serde_json::Error) -> Self {
+        SaveError::Format(err)
+    }
+}
+
+// Everything needed to carry on with a game: the position with how it was reached, the clocks and the game's
+// PGN tags, which name the players.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SavedGame {
+    pub tags: Vec<(String, String)>,
+    pub state: GameState,
+    pub clock: Option<Clock>,
+}
+
+// Only the version is read first, so that a later format that no longer fits SaveFile is still reported as such.
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile<T> {
+    version: u32,
+    game: T,
+}
+
+impl SavedGame {
+    // A game that hasn't started yet, from state, with no tags and no clock.
+    pub fn new(state: GameState) -> Self {
+        SavedGame { tags: vec![], state, clock: None }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&SaveFile { version: SAVE_VERSION, game: self }).expect("a saved game is always valid JSON")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, SaveError> {
+        let header: SaveHeader = serde_json::from_str(json)?;
+        if header.version > SAVE_VERSION {
+            return Err(SaveError::UnsupportedVersion(header.version));
+        }
+        let file: SaveFile<SavedGame> = serde_json::from_str(json)?;
+        Ok(file.game)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        Ok(std::fs::write(path, self.to_json())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SaveError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    // The game as a PGN record, from where it started and with its tags, for carrying on saving it as PGN. An
+    // error if the moves can't be recorded, as when they go on after the game was over.
+    pub fn record(&self) -> Result<PgnGame, ChessError> {
+        let mut record = PgnGame::new(self.state.start_position());
+        for (name, value) in self.tags.iter() {
+            record.set_tag(name, value);
+        }
+        for mv in self.state.move_history() {
+            record.push_move(mv)?;
+        }
+        record.set_outcome(self.state.outcome());
+        Ok(record)
+    }
+}
+
+impl GameState {
+    // The position the game was set up in, before any of the moves in move_history.
+    pub fn start_position(&self) -> GameState {
+        let mut start = self.clone();
+        while start.unmake_move().is_some() {}
+        start.redo_stack.clear();
+        start
+    }
+}
+
+// A position is saved as where the game started and the moves since, so that loading it rebuilds the repetition
+// history and the moves that can be taken back or replayed along with it.
+#[derive(Serialize, Deserialize)]
+struct SavedPosition {
+    variant: String,
+    start: String,
+    moves: Vec<String>,
+    // Moves taken back, next to be replayed first
+    undone: Vec<String>,
+}
+
+impl Serialize for GameState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedPosition {
+            variant: self.variant.to_string(),
+            start: self.start_position().to_fen(),
+            moves: self.move_history().iter().map(Move::to_string).collect(),
+            undone: self.redo_stack.iter().rev().map(Move::to_string).collect(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GameState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedPosition::deserialize(deserializer)?;
+        let variant: Variant = saved.variant.parse().map_err(D::Error::custom)?;
+        let mut state = GameState::from_variant_fen(variant, &saved.start).map_err(D::Error::custom)?;
+        for uci in saved.moves.iter().chain(saved.undone.iter()) {
+            // Legal moves can still run on past a draw by repetition or the fifty-move rule
+            if let Some(outcome) = state.outcome() {
+                return Err(D::Error::custom(format!("{} played after the game ended: {}", uci, outcome)));
+            }
+            let mv = state.parse_uci(uci).map_err(D::Error::custom)?;
+            state.do_move(mv);
+        }
+        for _ in saved.undone.iter() {
+            state.undo();
+        }
+        Ok(state)
+    }
+}
+
+// A clock is saved with each side's time as it stands when saved, and comes back stopped, to be started
+// again once play resumes.
+#[derive(Serialize, Deserialize)]
+struct SavedClock {
+    control: String,
+    // White then Black
+    remaining_ms: [u64; 2],
+}
+
+impl Serialize for Clock {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let now = Instant::now();
+        let remaining = |color| self.remaining(color, now).as_millis() as u64;
+        SavedClock { control: self.control.to_string(), remaining_ms: [remaining(Color::White), remaining(Color::Black)] }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Clock {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let saved = SavedClock::deserialize(deserializer)?;
+        let control: TimeControl = saved.control.parse().map_err(D::Error::custom)?;
+        let mut clock = Clock::new(control);
+        clock.set_remaining(Color::White, Duration::from_millis(saved.remaining_ms[0]));
+        clock.set_remaining(Color::Black, Duration::from_millis(saved.remaining_ms[1]));
+        Ok(clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_save_round_trip() {
+        let mut state = GameState::from_fen(STARTING_FEN).unwrap();
+        for uci in ["g1f3", "g8f6", "f3g1", "f6g8", "e2e4", "e7e5"] {
+            state.do_move(state.parse_uci(uci).unwrap());
+        }
+        state.undo();
+        let mut clock = Clock::new("300+5".parse().unwrap());
+        clock.set_remaining(Color::White, Duration::from_millis(281_500));
+        let game = SavedGame { tags: vec![("White".to_string(), "Alice".to_string())], state: state.clone(), clock: Some(clock) };
+        let loaded = SavedGame::from_json(&game.to_json()).unwrap();
+        assert_eq!(loaded.tags, game.tags);
+        assert_eq!(loaded.state.to_fen(), state.to_fen());
+        assert_eq!(loaded.state.move_history(), state.move_history());
+        assert_eq!(loaded.state.zobrist_hash(), state.zobrist_hash());
+        // The knights going out and back count towards a repetition, and the undone move can be replayed
+        let mut replayed = loaded.state.clone();
+        assert_eq!(replayed.redo().map(|mv| mv.to_string()), Some("e7e5".to_string()));
+        let clock = loaded.clock.clone().unwrap();
+        assert_eq!(clock.control.to_string(), "300+5");
+        assert_eq!(clock.running(), None);
+        assert_eq!(clock.remaining(Color::White, Instant::now()), Duration::from_millis(281_500));
+        let record = loaded.record().unwrap();
+        assert_eq!((record.tag("White"), record.moves.len()), (Some("Alice"), 5));
+
+        let variant = GameState::from_variant_fen(Variant::ThreeCheck, Variant::ThreeCheck.starting_fen()).unwrap();
+        let loaded = SavedGame::from_json(&SavedGame::new(variant).to_json()).unwrap();
+        assert_eq!(loaded.state.variant(), Variant::ThreeCheck);
+    }
+
+    #[test]
+    fn test_bad_saves() {
+        let json = SavedGame::new(GameState::from_fen(STARTING_FEN).unwrap()).to_json();
+        let future = json.replacen(&format!("\"version\": {}", SAVE_VERSION), "\"version\": 99", 1);
+        assert!(matches!(SavedGame::from_json(&future), Err(SaveError::UnsupportedVersion(99))));
+        let illegal = json.replacen("\"moves\": []", "\"moves\": [\"e2e5\"]", 1);
+        assert!(matches!(SavedGame::from_json(&illegal), Err(SaveError::Format(_))));
+        assert!(matches!(SavedGame::from_json("not json"), Err(SaveError::Format(_))));
+        // Moves after a threefold repetition has drawn the game
+        let repeated = ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8", "e2e4"].map(|uci| format!("\"{}\"", uci)).join(", ");
+        let past_end = json.replacen("\"moves\": []", &format!("\"moves\": [{}]", repeated), 1);
+        assert!(matches!(SavedGame::from_json(&past_end), Err(SaveError::Format(_))));
+    }
+}
@@ -0,0 +1,307 @@
+// Chess variants: the same board and pieces with a different setup, different moves allowed or a different
+// way to win. GameState checks its variant wherever the rules part from standard chess.
+use super::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    // The back rank pieces shuffled, with castling still putting king and rook on their usual files
+    Chess960,
+    // Also won by getting the king to one of the four centre squares
+    KingOfTheHill,
+    // Also won by giving check three times
+    ThreeCheck,
+    // A capture blows up the capturing piece and every piece but a pawn around it. Won by blowing up the enemy king.
+    Atomic,
+    // Captures are compulsory and the king is just another piece. Won by running out of moves.
+    Antichess,
+}
+
+pub const VARIANTS: [Variant; 6] = [Variant::Standard, Variant::Chess960, Variant::KingOfTheHill, Variant::ThreeCheck, Variant::Atomic, Variant::Antichess];
+
+// d4, e4, d5 and e5
+const CENTRE: Bitboard = 0x0000_0018_1800_0000;
+// Checks that win a Three-check game
+pub(super) const CHECKS_TO_WIN: u8 = 3;
+
+// Antichess pawns may also become kings.
+const ANTICHESS_PROMOTION_PIECES: [Piece; 5] = [Piece::Queen, Piece::Rook, Piece::Bishop, Piece::Knight, Piece::King];
+
+impl Variant {
+    // The usual starting position. Chess960 games normally start from chess960_fen instead.
+    pub fn starting_fen(&self) -> &'static str {
+        match self {
+            Variant::Antichess => "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            _ => STARTING_FEN
+        }
+    }
+
+    // Pieces a pawn may promote to, in the order they are offered by the promotion picker.
+    pub fn promotion_pieces(&self) -> &'static [Piece] {
+        match self {
+            Variant::Antichess => &ANTICHESS_PROMOTION_PIECES,
+            _ => &PROMOTION_PIECES
+        }
+    }
+}
+
+// The names used by the PGN Variant tag.
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Variant::Standard => "Standard",
+            Variant::Chess960 => "Chess960",
+            Variant::KingOfTheHill => "King of the Hill",
+            Variant::ThreeCheck => "Three-check",
+            Variant::Atomic => "Atomic",
+            Variant::Antichess => "Antichess",
+        })
+    }
+}
+
+// Why a string is not a variant name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantParseError {
+    name: String
+}
+impl fmt::Display for VariantParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = VARIANTS.iter().map(|variant| variant.to_string()).collect();
+        write!(f, "Unknown variant '{}', expected one of {}", self.name, names.join(", "))
+    }
+}
+impl std::error::Error for VariantParseError {}
+
+// Any case, with or without spaces and dashes, and a few common short names.
+impl FromStr for Variant {
+    type Err = VariantParseError;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let key: String = name.chars().filter(|c| !matches!(c, ' ' | '-' | '_')).collect::<String>().to_ascii_lowercase();
+        match key.as_str() {
+            "standard" | "chess" => Ok(Variant::Standard),
+            "chess960" | "960" | "fischerandom" => Ok(Variant::Chess960),
+            "kingofthehill" | "koth" => Ok(Variant::KingOfTheHill),
+            "threecheck" | "3check" => Ok(Variant::ThreeCheck),
+            "atomic" => Ok(Variant::Atomic),
+            "antichess" | "giveaway" => Ok(Variant::Antichess),
+            _ => Err(VariantParseError { name: name.to_string() })
+        }
+    }
+}
+
+// Chess960 starting position number (0 to 959, anything bigger wraps around) in Scharnagl's numbering,
+// where 518 is the standard setup.
+pub fn chess960_fen(number: usize) -> String {
+    // Knight placements among the five squares left once bishops and queen are down
+    const KNIGHTS: [(usize, usize); 10] = [(0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4)];
+    let mut rank = [' '; 8];
+    let mut n = number % 960;
+    rank[2 * (n % 4) + 1] = 'b';
+    n /= 4;
+    rank[2 * (n % 4)] = 'b';
+    n /= 4;
+    let empty = |rank: &[char; 8]| (0..8).filter(|&x| rank[x] == ' ').collect::<Vec<usize>>();
+    rank[empty(&rank)[n % 6]] = 'q';
+    n /= 6;
+    let (first, second) = KNIGHTS[n];
+    let left = empty(&rank);
+    rank[left[first]] = 'n';
+    rank[left[second]] = 'n';
+    // Rook, king, rook on what is left, so the king always stands between its rooks
+    for (x, piece) in empty(&rank).into_iter().zip(['r', 'k', 'r']) {
+        rank[x] = piece;
+    }
+    let black: String = rank.iter().collect();
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", black, black.to_ascii_uppercase())
+}
+
+impl GameState {
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    // Checks given so far by color, for Three-check.
+    pub fn checks_given(&self, color: Color) -> u8 {
+        self.checks_given[bitboard::color_index(color)]
+    }
+
+    // Atomic: a capture on pos blows up the capturing piece there and every piece but a pawn next to it.
+    // Returns what was removed, for unmake_move to put back.
+    pub(super) fn explode(&mut self, pos: Square) -> Vec<(Square, BoardSquare)> {
+        let mut exploded = vec![(pos, self.board_state[pos.1][pos.0])];
+        for near in bitboard::squares(bitboard::KING_ATTACKS[square_index(pos)] & self.bitboards.occupied()) {
+            let sq = self.board_state[near.1][near.0];
+            if !matches!(sq, BoardSquare::Occupied(Piece::Pawn(_), _)) {
+                exploded.push((near, sq));
+            }
+        }
+        for &(pos, _) in exploded.iter() {
+            self.set_square(pos, BoardSquare::Empty);
+        }
+        exploded
+    }
+
+    // Atomic: whether mv keeps the mover's king on the board and out of check. Blowing up the enemy king wins
+    // at once, check or not, and kings standing next to each other can't be checked as neither may capture.
+    pub(super) fn keeps_atomic_king(&mut self, mv: Move) -> bool {
+        let mover = self.turn;
+        self.make_move(mv);
+        let safe = match (self.bitboards.king(mover), self.bitboards.king(!mover)) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(own), Some(_)) => !self.is_atomic_check(own, mover)
+        };
+        self.unmake_move();
+        safe
+    }
+
+    pub(super) fn is_atomic_check(&self, king: Square, color: Color) -> bool {
+        bitboard::KING_ATTACKS[square_index(king)] & self.bitboards.pieces(Piece::King, !color) == 0 && self.is_square_attacked(king, !color)
+    }
+
+    // Antichess: whether the side to move has a capture, which it then has to make.
+    pub(super) fn has_capture(&self) -> bool {
+        bitboard::squares(self.bitboards.color(self.turn)).any(|from_pos| match self.board_state[from_pos.1][from_pos.0] {
+            BoardSquare::Occupied(piece, _) => self.candidate_moves(from_pos, piece).iter().any(|mv| mv.capture),
+            BoardSquare::Empty => false
+        })
+    }
+
+    // A win the variant has on top of checkmate, if one has happened.
+    pub(super) fn variant_outcome(&self) -> Option<GameOutcome> {
+        for color in [Color::White, Color::Black] {
+            let won = match self.variant {
+                Variant::KingOfTheHill => self.bitboards.pieces(Piece::King, color) & CENTRE != 0,
+                Variant::ThreeCheck => self.checks_given(color) >= CHECKS_TO_WIN,
+                Variant::Atomic => self.bitboards.king(!color).is_none(),
+                _ => false
+            };
+            if won {
+                return Some(match self.variant {
+                    Variant::KingOfTheHill => GameOutcome::KingOfTheHill { winner: color },
+                    Variant::ThreeCheck => GameOutcome::ThreeChecks { winner: color },
+                    _ => GameOutcome::KingExploded { winner: color }
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn play(state: &mut GameState, moves: &[&str]) {
+        for uci in moves {
+            state.do_move(state.parse_uci(uci).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_variant_names() {
+        for variant in VARIANTS {
+            assert_eq!(variant.to_string().parse(), Ok(variant));
+        }
+        assert_eq!("king-of-the-hill".parse(), Ok(Variant::KingOfTheHill));
+        assert_eq!("3check".parse(), Ok(Variant::ThreeCheck));
+        assert!("crazyhouse".parse::<Variant>().is_err());
+    }
+
+    #[test]
+    fn test_chess960() {
+        assert_eq!(chess960_fen(518), STARTING_FEN);
+        assert_eq!(chess960_fen(0), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        let start = GameState::from_variant_fen(Variant::Chess960, &chess960_fen(0)).unwrap();
+        assert_eq!(start.to_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1");
+        // Published counts for a position with castling either way
+        let state = GameState::from_variant_fen(Variant::Chess960, "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9").unwrap();
+        assert_eq!(state.perft(1), 21);
+        assert_eq!(state.perft(2), 528);
+        assert_eq!(state.perft(3), 12189);
+
+        // King and rook swap over: the king takes its own rook, and undo puts both back
+        let mut state = GameState::from_variant_fen(Variant::Chess960, "4k3/8/8/8/8/8/8/5KR1 w G - 0 1").unwrap();
+        let castle = state.parse_uci("f1g1").unwrap();
+        assert_eq!(castle.castle, Some(CastleSide::KingSide));
+        assert_eq!(state.move_to_san(castle), "O-O");
+        state.do_move(castle);
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/5RK1 b - - 1 1");
+        assert_eq!(state.zobrist_hash(), state.compute_hash());
+        state.undo();
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/5KR1 w G - 0 1");
+        // A rook attacked through the king's path is fine, the king's own path is not
+        let state = GameState::from_variant_fen(Variant::Chess960, "1r2k3/8/8/8/8/8/8/1R2K3 w B - 0 1").unwrap();
+        assert!(state.legal_moves().iter().any(|mv| mv.castle == Some(CastleSide::QueenSide)));
+        let state = GameState::from_variant_fen(Variant::Chess960, "2r1k3/8/8/8/8/8/8/1R2K3 w B - 0 1").unwrap();
+        assert!(state.legal_moves().iter().all(|mv| mv.castle.is_none()));
+    }
+
+    #[test]
+    fn test_king_of_the_hill() {
+        // Bare kings aren't a draw when either could walk to the centre
+        let mut state = GameState::from_variant_fen(Variant::KingOfTheHill, "4k3/8/8/8/8/4K3/8/8 w - - 0 1").unwrap();
+        assert_eq!(state.outcome(), None);
+        play(&mut state, &["e3e4"]);
+        assert_eq!(state.outcome(), Some(GameOutcome::KingOfTheHill { winner: Color::White }));
+    }
+
+    #[test]
+    fn test_three_check() {
+        let mut state = GameState::from_variant_fen(Variant::ThreeCheck, STARTING_FEN).unwrap();
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1");
+        play(&mut state, &["e2e4", "e7e5", "f1c4", "d7d6", "c4f7", "e8f7", "d1h5", "g7g6"]);
+        assert_eq!(state.checks_given(Color::White), 2);
+        let fen = state.to_fen();
+        assert!(fen.contains(" 1+3 "), "{}", fen);
+        let reloaded = GameState::from_variant_fen(Variant::ThreeCheck, &fen).unwrap();
+        assert_eq!(reloaded.zobrist_hash(), state.zobrist_hash());
+        assert_eq!(state.outcome(), None);
+        play(&mut state, &["h5g6"]);
+        assert_eq!(state.outcome(), Some(GameOutcome::ThreeChecks { winner: Color::White }));
+        while state.undo().is_some() {}
+        assert_eq!(state.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 3+3 0 1");
+    }
+
+    #[test]
+    fn test_atomic() {
+        let start = GameState::from_variant_fen(Variant::Atomic, STARTING_FEN).unwrap();
+        assert_eq!(start.perft(3), 8902);
+        // Nxf7 blows up the knight and everything but pawns around f7, the king included
+        let mut state = GameState::from_variant_fen(Variant::Atomic, "rnbqkb1r/pppppppp/7n/6N1/8/8/PPPPPPPP/RNBQKB1R w KQkq - 0 1").unwrap();
+        let before = state.to_fen();
+        play(&mut state, &["g5f7"]);
+        assert_eq!(state.to_fen(), "rnbq3r/ppppp1pp/7n/8/8/8/PPPPPPPP/RNBQKB1R b KQ - 0 1");
+        assert_eq!(state.outcome(), Some(GameOutcome::KingExploded { winner: Color::White }));
+        assert_eq!(state.zobrist_hash(), state.compute_hash());
+        state.undo();
+        assert_eq!(state.to_fen(), before);
+        // Kings can't capture, and a king next to the enemy king is never in check
+        let state = GameState::from_variant_fen(Variant::Atomic, "8/8/8/3k4/3Kr3/8/8/8 w - - 0 1").unwrap();
+        assert!(state.find_move(Square(3, 4), Square(4, 4), None).is_none());
+        assert!(!state.is_in_check());
+        // Capturing next to your own king blows it up
+        let state = GameState::from_variant_fen(Variant::Atomic, "4k3/8/8/8/8/8/3p4/1N2K3 w - - 0 1").unwrap();
+        assert!(state.legal_moves().iter().all(|mv| !mv.capture));
+    }
+
+    #[test]
+    fn test_antichess() {
+        let start = GameState::from_variant_fen(Variant::Antichess, Variant::Antichess.starting_fen()).unwrap();
+        assert_eq!(start.perft(3), 8067);
+        // Any capture must be taken, and the king may walk into check
+        let mut state = start.clone();
+        play(&mut state, &["e2e3", "b7b5"]);
+        let moves = state.legal_moves();
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].to_string(), "f1b5");
+        let state = GameState::from_variant_fen(Variant::Antichess, "8/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        assert!(state.find_move(Square(0, 7), Square(1, 6), None).is_some());
+        let promoting = GameState::from_variant_fen(Variant::Antichess, "8/4P3/8/8/8/8/8/k7 w - - 0 1").unwrap();
+        assert!(promoting.find_move(Square(4, 1), Square(4, 0), Some(Piece::King)).is_some());
+        // Losing everything wins
+        let mut state = GameState::from_variant_fen(Variant::Antichess, "8/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        play(&mut state, &["a1b2"]);
+        assert_eq!(state.outcome(), Some(GameOutcome::NoMovesLeft { winner: Color::Black }));
+    }
+}
@@ -0,0 +1,77 @@
+// Running the board in a minifb window: drawing it, passing it keys, and waiting for the mouse while the clocks
+// count down on screen. Hotseat and network games both go through here, so they behave the same way.
+use super::board::{Board, Drawable, Gesture, ScreenPosition};
+use cairo::{ Context, ImageSurface };
+use minifb::{KeyRepeat, MouseButton, MouseMode, Window};
+use std::error::Error;
+use std::time::Duration;
+
+// How often the window is updated while waiting.
+pub const FRAME_TIME: Duration = Duration::from_millis(20);
+
+// Draw the board onto surface and show it in window.
+pub fn draw(window: &mut Window, surface: &mut ImageSurface, board: &Board) -> Result<(), Box<dyn Error>> {
+    let size = window.get_size();
+    {
+        let mut context = Context::new(surface)?;
+
+        context.set_source_rgb(1.0, 1.0, 1.0);
+        context.paint()?;
+
+        board.draw(&mut context, ScreenPosition(0.0, 0.0))?;
+    }
+    let data = surface.data();
+    let uwdata = data.unwrap();
+    let data = unsafe {
+        let (_, d, _) = uwdata.align_to::<u32>();
+        d
+    };
+    window.update_with_buffer(data, size.0, size.1)?;
+    Ok(())
+}
+
+// Let the board act on each key pressed since the window was last updated. Whether anything changed, so the
+// board needs drawing again.
+pub fn handle_keys(window: &Window, board: &mut Board) -> bool {
+    let mut changed = false;
+    for key in window.get_keys_pressed(KeyRepeat::No) {
+        changed |= board.handle_key(key);
+    }
+    changed
+}
+
+// Let the window handle its events, redrawing if a clock is running so that it counts down on screen, or if
+// the board has been turned round or an overlay toggled.
+pub fn tick(window: &mut Window, surface: &mut ImageSurface, board: &mut Board) -> Result<(), Box<dyn Error>> {
+    if handle_keys(window, board) || board.clock.as_ref().is_some_and(|clock| clock.running().is_some()) {
+        draw(window, surface, board)
+    } else {
+        window.update();
+        Ok(())
+    }
+}
+
+// Wait for a click on the board or a piece to be dragged and dropped, keeping the clocks on screen up to date
+// and a dragged piece under the mouse. None if the side to move's flag falls first.
+pub fn next_gesture(window: &mut Window, surface: &mut ImageSurface, board: &mut Board) -> Result<Option<Gesture>, Box<dyn Error>> {
+    window.limit_update_rate(Some(FRAME_TIME));
+    loop {
+        if board.flagged() == Some(board.game_state.turn) {
+            board.cancel_gesture();
+            return Ok(None);
+        }
+        let pos = window.get_mouse_pos(MouseMode::Clamp).map(|pos| ScreenPosition(pos.0 as f64, pos.1 as f64));
+        let was_dragging = board.dragged().is_some();
+        let gesture = board.pointer(pos, window.get_mouse_down(MouseButton::Left));
+        if board.dragged().is_some() || was_dragging {
+            // Follow the mouse, or put a dropped piece back until the move is played
+            handle_keys(window, board);
+            draw(window, surface, board)?;
+        } else {
+            tick(window, surface, board)?;
+        }
+        if let Some(gesture) = gesture {
+            return Ok(Some(gesture));
+        }
+    }
+}
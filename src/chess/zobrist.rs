@@ -0,0 +1,187 @@
+// Zobrist hashing: a 64-bit key per position, kept up to date by do_move and undo by XORing out what
+// a move changes and XORing the new contents back in.
+use super::*;
+
+struct ZobristKeys {
+    // Indexed by piece_index, then by y * 8 + x
+    pieces: [[u64; 64]; 12],
+    // Black left, black right, white left, white right
+    castling: [u64; 4],
+    // By file of the pawn that can be taken en passant
+    en_passant: [u64; 8],
+    black_to_move: u64,
+    // Three-check: by color_index, then checks given less one
+    checks: [[u64; 3]; 2],
+}
+
+// One step of the splitmix64 generator, returning the new state and the next random number.
+pub(super) const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+// Fixed seed, so that every build and both network peers agree on the keys.
+const fn zobrist_keys() -> ZobristKeys {
+    let mut keys = ZobristKeys { pieces: [[0; 64]; 12], castling: [0; 4], en_passant: [0; 8], black_to_move: 0, checks: [[0; 3]; 2] };
+    let mut state = 0x5EED_C4E5_5000_0001;
+    let mut i = 0;
+    while i < 12 * 64 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.pieces[i / 64][i % 64] = key;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 4 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.castling[i] = key;
+        i += 1;
+    }
+    let mut i = 0;
+    while i < 8 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.en_passant[i] = key;
+        i += 1;
+    }
+    let (mut state, black_to_move) = splitmix64(state);
+    keys.black_to_move = black_to_move;
+    let mut i = 0;
+    while i < 6 {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys.checks[i / 3][i % 3] = key;
+        i += 1;
+    }
+    keys
+}
+
+static ZOBRIST: ZobristKeys = zobrist_keys();
+
+fn piece_index(piece: Piece, color: Color) -> usize {
+    let kind = bitboard::piece_kind(piece);
+    if color == Color::White {kind} else {kind + 6}
+}
+
+impl GameState {
+    pub fn zobrist_hash(&self) -> u64 {
+        self.hash
+    }
+
+    fn square_hash(&self, pos: Square) -> u64 {
+        match self.board_state[pos.1][pos.0] {
+            BoardSquare::Occupied(piece, color) => ZOBRIST.pieces[piece_index(piece, color)][pos.1 * 8 + pos.0],
+            BoardSquare::Empty => 0
+        }
+    }
+
+    // Everything but the pieces: side to move, castling rights, an en passant square only when there is
+    // an enemy pawn next to it that could take, so that repetitions aren't missed, and Three-check's checks.
+    pub(super) fn state_hash(&self) -> u64 {
+        let mut hash = 0;
+        if self.turn == Color::Black {
+            hash ^= ZOBRIST.black_to_move;
+        }
+        let castling = [self.black_can_castle_left, self.black_can_castle_right, self.white_can_castle_left, self.white_can_castle_right];
+        for (i, can_castle) in castling.iter().enumerate() {
+            if *can_castle {
+                hash ^= ZOBRIST.castling[i];
+            }
+        }
+        if let Some(pos) = self.capturable_en_passant() {
+            hash ^= ZOBRIST.en_passant[pos.0];
+        }
+        for (i, &checks) in self.checks_given.iter().enumerate() {
+            if checks > 0 {
+                hash ^= ZOBRIST.checks[i][(checks as usize).min(3) - 1];
+            }
+        }
+        hash
+    }
+
+    // The en passant square, if a pawn of the side to move stands next to it ready to take.
+    pub(super) fn capturable_en_passant(&self) -> Option<Square> {
+        self.en_passant_square.filter(|pos| {
+            [pos.0.wrapping_sub(1), pos.0 + 1].iter().any(|&x| x < 8 && matches!(self.board_state[pos.1][x], BoardSquare::Occupied(Piece::Pawn(_), color) if color == self.turn))
+        })
+    }
+
+    // XOR the given squares and the non-piece state in or out of the hash. Called once before a change
+    // to take the old contents out and once after to put the new ones in.
+    pub(super) fn toggle_hash(&mut self, squares: &[Square]) {
+        let mut hash = self.state_hash();
+        for pos in squares {
+            hash ^= self.square_hash(*pos);
+        }
+        self.hash ^= hash;
+    }
+
+    // The hash worked out from scratch.
+    pub(super) fn compute_hash(&self) -> u64 {
+        (0..64).map(|i| self.square_hash(Square(i % 8, i / 8))).fold(self.state_hash(), |hash, key| hash ^ key)
+    }
+}
+
+impl GameState {
+    // Squares a move changes, each once: its start and end, the pawn taken en passant, the castling king's
+    // and rook's starts and ends, and in Atomic every square around a capture.
+    pub(super) fn touched_squares(&self, mv: &Move) -> Vec<Square> {
+        let mut squares = vec![mv.from, mv.to];
+        if mv.en_passant {
+            squares.push(mv.captured_square());
+        }
+        if let Some(side) = mv.castle {
+            let color = if mv.from.1 == 7 {Color::White} else {Color::Black};
+            let (king_to, rook_to) = castle_destinations(side, mv.from.1);
+            squares.extend([self.castling_rook(color, side), king_to, rook_to]);
+        }
+        if mv.capture && self.variant == Variant::Atomic {
+            squares.extend(bitboard::squares(bitboard::KING_ATTACKS[square_index(mv.to)]));
+        }
+        // A square counted twice would cancel itself out of the hash
+        squares.sort_by_key(|&pos| square_index(pos));
+        squares.dedup();
+        squares
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_zobrist() {
+        let mut state = GameState::from_fen("r3k2r/1P6/8/8/5p2/8/4P3/R3K2R w KQkq - 0 1").unwrap();
+        let start_hash = state.zobrist_hash();
+        assert_eq!(start_hash, state.compute_hash());
+        let moves = [(Square(4, 7), Square(6, 7), None), (Square(7, 0), Square(7, 3), None),
+                     (Square(4, 6), Square(4, 4), None), (Square(5, 4), Square(4, 5), None),
+                     (Square(1, 1), Square(0, 0), Some(Piece::Rook))];
+        let mut hashes = vec![];
+        for (from_pos, to_pos, promotion) in moves {
+            hashes.push(state.zobrist_hash());
+            state.do_move(state.find_move(from_pos, to_pos, promotion).unwrap());
+            assert_eq!(state.zobrist_hash(), state.compute_hash());
+            assert_eq!(state.zobrist_hash(), GameState::from_fen(&state.to_fen()).unwrap().zobrist_hash());
+        }
+        while state.undo().is_some() {
+            assert_eq!(state.zobrist_hash(), hashes.pop().unwrap());
+        }
+        assert_eq!(state.zobrist_hash(), start_hash);
+
+        // Same placement reached by different move orders hashes the same; side to move matters
+        let mut a = GameState::from_fen(STARTING_FEN).unwrap();
+        let mut b = a.clone();
+        for (from_pos, to_pos) in [(Square(6, 7), Square(5, 5)), (Square(6, 0), Square(5, 2)), (Square(1, 7), Square(2, 5))] {
+            a.do_move(a.find_move(from_pos, to_pos, None).unwrap());
+        }
+        for (from_pos, to_pos) in [(Square(1, 7), Square(2, 5)), (Square(6, 0), Square(5, 2)), (Square(6, 7), Square(5, 5))] {
+            b.do_move(b.find_move(from_pos, to_pos, None).unwrap());
+        }
+        assert_eq!(a.zobrist_hash(), b.zobrist_hash());
+        assert_ne!(a.zobrist_hash(), GameState::from_fen(&a.to_fen().replace(" b ", " w ")).unwrap().zobrist_hash());
+    }
+}
@@ -1,4 +1,9 @@
 mod graph;
+// The rules of chess, with no GUI or networking needed
+pub mod chess;
+// Playing a game against another instance over TCP, in a window
+#[cfg(feature = "net")]
+pub mod net_chess;
 pub type Result<T> = std::result::Result<T, &'static str>;
 
 pub fn run(upto: usize) -> Result<()> {
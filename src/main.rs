@@ -1,49 +1,197 @@
 use my_project;
+#[cfg(feature = "gui")]
 mod raqote_example;
-mod net_chess;
-//mod chess;
+mod uci;
 use std::env;
-use minifb::{MouseMode, MouseButton, Window, WindowOptions};
-extern crate cairo;
-use cairo::{ ImageSurface, Format, Context };
 use std::error::Error;
-
-const WIDTH: usize = 400;
-const HEIGHT: usize = 400;
-//static global_state: Option<net_chess::GlobalState> = None;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use my_project::chess::{GameState, Variant};
+use my_project::chess::clock::Clock;
+use my_project::chess::save::SavedGame;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    println!("Hello, world!");
     let args: Vec<String> = env::args().collect();
-    let s_or_c = &args[1];
+    // Anything else printed on stdout would confuse the GUI on the other end
+    if args.get(1).map(String::as_str) == Some("uci") {
+        return uci::run(std::io::stdin().lock(), std::io::stdout());
+    }
+    println!("Hello, world!");
+    let mut args = args;
+    let variant = take_option(&mut args, "--variant").map(|name| name.parse::<Variant>()).transpose()?.unwrap_or_default();
+    match args.get(1).map(String::as_str) {
+        Some("perft") => return run_perft(&args[2..], variant),
+        Some("book") => return build_book(&args[2..]),
+        Some("suggest") => return suggest_move(&args[2..]),
+        _ => {}
+    }
+    let time_control = take_option(&mut args, "--clock").map(|tc| tc.parse()).transpose()?;
+    let save_path = take_option(&mut args, "--save");
+    let assets = take_option(&mut args, "--assets");
+    let font = take_option(&mut args, "--font");
+    // A resumed game keeps its own variant and clocks
+    let resume = take_option(&mut args, "--resume");
+    if resume.is_some() && args.get(1).map(String::as_str) == Some("c") {
+        return Err("Only the host can resume a game; the other side joins as usual".into());
+    }
+    let game = match resume {
+        Some(path) => SavedGame::load(path)?,
+        None => {
+            let mut game = SavedGame::new(starting_position(variant)?);
+            game.clock = time_control.map(Clock::new);
+            game
+        }
+    };
+    if args.get(1).map(String::as_str) == Some("hotseat") {
+        return run_hotseat_game(&args, save_path, game, assets.as_deref(), font.as_deref());
+    }
+    run_network_game(&args, save_path, game, assets.as_deref(), font.as_deref())
+//    // let gs = Rc::new(net_chess::GlobalState);
+//     //raqote_example::main();
+
+//     //my_project::run(1500)?;
+
+//     Ok(())
+}
+
+// Remove "<name> <value>" from args wherever it is, returning the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let at = args.iter().position(|arg| arg == name)?;
+    let value = args.get(at + 1).cloned();
+    args.drain(at..(at + 2).min(args.len()));
+    value
+}
+
+// The position a new game of variant starts from, with a Chess960 setup picked at random.
+fn starting_position(variant: Variant) -> Result<GameState, Box<dyn Error>> {
+    if variant == Variant::Chess960 {
+        let number = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as usize % 960;
+        println!("Chess960 position {}", number);
+        return Ok(GameState::from_variant_fen(variant, &my_project::chess::variant::chess960_fen(number))?);
+    }
+    Ok(GameState::from_variant_fen(variant, variant.starting_fen())?)
+}
+
+// hotseat [pgn file] [--clock <time control>] [--variant <variant>] [--save <file>] [--resume <file>]
+// [--assets <dirs>] [--font <family>]: both sides play in one window, moving by clicking a piece and then its
+// square or by dragging it there. With --save the game is saved after every move, for --resume to carry on with
+// later. --assets lists directories to look for piece images in before CHESS_ASSETS and the config file,
+// separated like PATH, and --font names the font for the board's coordinates, ahead of CHESS_FONT and the
+// config file. F turns the board round, and M, C, L and K show or hide the dots on
+// the selected piece's moves, the rings round what it can take, the last move's squares and the check highlight.
+#[cfg(feature = "gui")]
+fn run_hotseat_game(args: &[String], save_path: Option<String>, game: SavedGame, assets: Option<&str>, font: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let assets = my_project::chess::assets::Assets::configure(assets, font)?;
+    raqote_example::main(args.get(2).map(String::as_str), save_path.as_deref(), game, &assets)
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_hotseat_game(_: &[String], _: Option<String>, _: SavedGame, _: Option<&str>, _: Option<&str>) -> Result<(), Box<dyn Error>> {
+    Err("Hotseat games need the gui feature".into())
+}
+
+// s|c <port> [pgn file] [--clock <time control>] [--variant <variant>] [--save <file>] [--resume <file>]
+// [--assets <dirs>] [--font <family>]: host or join a game over TCP. The host's time control, in seconds like 300, 300+5 for an increment or 300d5 for a delay,
+// and variant, such as chess960, kingofthehill, threecheck, atomic or antichess, are used by both sides. Only the
+// host resumes a saved game, which is sent to the other side so both carry on from it. Each side sees its own
+// pieces at the bottom, and the keys are as in hotseat games.
+#[cfg(feature = "net")]
+fn run_network_game(args: &[String], save_path: Option<String>, game: SavedGame, assets: Option<&str>, font: Option<&str>) -> Result<(), Box<dyn Error>> {
+    use minifb::{Window, WindowOptions};
+    use cairo::{ ImageSurface, Format };
+    use my_project::{chess, net_chess};
+    const WIDTH: usize = 400;
+    const HEIGHT: usize = 400;
+    const USAGE: &str = "Usage: s|c <port> [pgn file] [--clock <time control>] [--variant <variant>] [--save <file>] [--resume <file>] [--assets <dirs>] [--font <family>]";
+
+    let s_or_c = args.get(1).ok_or(USAGE)?;
     let is_client = match s_or_c.as_ref() {
         "s" => false,
         "c" => true,
-        _ => panic!("Invalid c or s string!")
+        _ => return Err("Invalid c or s string!".into())
     };
  
-    let sport = &args[2];
+    let sport = args.get(2).ok_or(USAGE)?;
     let port = sport.parse::<usize>()?;
+    // Optional file to save the game to as PGN
+    let files = net_chess::GameFiles { pgn: args.get(3).cloned(), save: save_path };
 
-    let window = Window::new("Raqote", WIDTH, HEIGHT, WindowOptions {
+    let window = Window::new("Raqote", WIDTH + chess::SIDE_PANEL_WIDTH as usize, HEIGHT, WindowOptions {
         ..WindowOptions::default()
-    }).unwrap();
+    })?;
     let size = window.get_size();
-    let surface = ImageSurface::create(Format::ARgb32, size.0 as i32, size.1 as i32)
-        .expect("Couldn’t create surface");
-    let mut board = net_chess::chess::Board::new(WIDTH as f64, HEIGHT as f64);
-    board.setup_new_game();
+    let surface = ImageSurface::create(Format::ARgb32, size.0 as i32, size.1 as i32)?;
+    let board = chess::Board::with_assets(WIDTH as f64, HEIGHT as f64, &chess::assets::Assets::configure(assets, font)?)?;
 
     if is_client {
-        net_chess::run_client(board, window, surface, port, WIDTH, HEIGHT)?;
+        net_chess::run_client(board, window, surface, port, files)?;
     } else {
-        net_chess::run_server(board, window, surface, port, WIDTH, HEIGHT)?;
+        net_chess::run_server(board, game, window, surface, port, files)?;
     }
     Ok(())
-//    // let gs = Rc::new(net_chess::GlobalState);
-//     //raqote_example::main();
+}
 
-//     //my_project::run(1500)?;
+#[cfg(not(feature = "net"))]
+fn run_network_game(_: &[String], _: Option<String>, _: SavedGame, _: Option<&str>, _: Option<&str>) -> Result<(), Box<dyn Error>> {
+    Err("Network games need the net feature".into())
+}
 
-//     Ok(())
+// perft <depth> [fen] [--variant <variant>]: count the move tree from the position, starting position by default,
+// move by move.
+fn run_perft(args: &[String], variant: Variant) -> Result<(), Box<dyn Error>> {
+    let depth = args.first().ok_or("Usage: perft <depth> [fen] [--variant <variant>]")?.parse::<usize>()?;
+    let fen = if args.len() > 1 {args[1..].join(" ")} else {variant.starting_fen().to_string()};
+    let state = GameState::from_variant_fen(variant, &fen)?;
+    let start = Instant::now();
+    let divided = state.divide(depth);
+    for (mv, nodes) in divided.iter() {
+        println!("{}: {}", mv, nodes);
+    }
+    let total = if depth == 0 {1} else {divided.iter().map(|(_, nodes)| nodes).sum()};
+    let elapsed = start.elapsed().as_secs_f64();
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {:.3}s ({:.0} nodes/s)", elapsed, total as f64 / elapsed.max(1e-9));
+    Ok(())
+}
+
+// book <out.bin> <pgn file>...: build a Polyglot opening book from the first moves of every game in the files.
+fn build_book(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use my_project::chess::{pgn, polyglot};
+    const USAGE: &str = "Usage: book <out.bin> <pgn file>...";
+    let out = args.first().ok_or(USAGE)?;
+    if args.len() < 2 {
+        return Err(USAGE.into());
+    }
+    let mut builder = polyglot::BookBuilder::new(polyglot::DEFAULT_BOOK_PLIES);
+    let mut games = 0;
+    for path in args[1..].iter() {
+        for game in pgn::parse_pgn(&std::fs::read_to_string(path)?)? {
+            builder.add_game(&game);
+            games += 1;
+        }
+    }
+    let book = builder.build();
+    book.save(out)?;
+    println!("Wrote {} entries from {} games to {}", book.entries().len(), games, out);
+    Ok(())
+}
+
+// suggest <book.bin> [fen]: list the book moves for the position, starting position by default, and pick one.
+fn suggest_move(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use my_project::chess::STARTING_FEN;
+    use my_project::chess::polyglot::Book;
+    let book = Book::open(args.first().ok_or("Usage: suggest <book.bin> [fen]")?)?;
+    let fen = if args.len() > 1 {args[1..].join(" ")} else {STARTING_FEN.to_string()};
+    let state = GameState::from_fen(&fen)?;
+    let moves = book.moves(&state);
+    let total: u64 = moves.iter().map(|(_, weight)| *weight as u64).sum();
+    for (mv, weight) in moves.iter() {
+        println!("{}: {} ({:.1}%)", state.move_to_san(*mv), weight, 100.0 * *weight as f64 / total.max(1) as f64);
+    }
+    let random = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+    match book.pick(&state, random) {
+        Some(mv) => println!("Suggested move: {}", state.move_to_san(mv)),
+        None => println!("The position is not in the book")
+    }
+    Ok(())
 }
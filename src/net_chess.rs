@@ -1,20 +1,26 @@
-#[path = "chess.rs"] pub mod chess;
+use crate::chess;
+use crate::chess::clock::{Clock, TimeControl};
+use crate::chess::save::SavedGame;
+use crate::chess::window;
 use std::str::from_utf8;
-use minifb::{MouseMode, MouseButton, Window, WindowOptions};
+use minifb::Window;
 extern crate cairo;
-use cairo::{ ImageSurface, Format, Context };
+use cairo::ImageSurface;
 use std::io::prelude::*;
 use std::net::{TcpListener, TcpStream};
 use std::error::Error;
+use std::time::{Duration, Instant};
 
 pub trait Networkable where Self: Sized {
     fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>>;
     fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>>;
 }
 
-impl Networkable for chess::BoardPosition {
+impl Networkable for chess::Square {
     fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
-        assert!(self.0 < 256 && self.1 < 256);
+        if !self.is_on_board() {
+            return Err(Box::new(chess::ChessError::OffBoard(*self)));
+        }
         stream.write_all(&[self.0 as u8, self.1 as u8])?;
         Ok(())
     }
@@ -22,7 +28,77 @@ impl Networkable for chess::BoardPosition {
     fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
         let mut buf = [0; 2];
         stream.read_exact(&mut buf)?;
-        Ok(chess::BoardPosition(buf[0] as usize, buf[1] as usize))
+        let pos = chess::Square(buf[0] as usize, buf[1] as usize);
+        if !pos.is_on_board() {
+            return Err(Box::new(BadPacketError {}));
+        }
+        Ok(pos)
+    }
+}
+
+// A promotion choice goes over the wire as a single byte, with 0 meaning no promotion.
+impl Networkable for Option<chess::Piece> {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        let byte = match self {
+            None => 0,
+            Some(chess::Piece::Queen) => 1,
+            Some(chess::Piece::Rook) => 2,
+            Some(chess::Piece::Bishop) => 3,
+            Some(chess::Piece::Knight) => 4,
+            // Antichess only
+            Some(chess::Piece::King) => 5,
+            Some(piece) => return Err(Box::new(chess::ChessError::InvalidPromotion(*piece)))
+        };
+        stream.write_all(&[byte])?;
+        Ok(())
+    }
+
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf)?;
+        match buf[0] {
+            0 => Ok(None),
+            1 => Ok(Some(chess::Piece::Queen)),
+            2 => Ok(Some(chess::Piece::Rook)),
+            3 => Ok(Some(chess::Piece::Bishop)),
+            4 => Ok(Some(chess::Piece::Knight)),
+            5 => Ok(Some(chess::Piece::King)),
+            _ => Err(Box::new(BadPacketError {}))
+        }
+    }
+}
+
+// A move goes over the wire as its two squares, its promotion piece and a byte of flags.
+impl Networkable for chess::Move {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        self.from.serialize(stream)?;
+        self.to.serialize(stream)?;
+        self.promotion.serialize(stream)?;
+        let flags = match self.castle {
+            None => 0,
+            Some(chess::CastleSide::KingSide) => 4,
+            Some(chess::CastleSide::QueenSide) => 8
+        } | if self.capture {1} else {0} | if self.en_passant {2} else {0};
+        stream.write_all(&[flags])?;
+        Ok(())
+    }
+
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let from = chess::Square::deserialize(stream)?;
+        let to = chess::Square::deserialize(stream)?;
+        let promotion = Option::<chess::Piece>::deserialize(stream)?;
+        let mut flags = [0; 1];
+        stream.read_exact(&mut flags)?;
+        let castle = match flags[0] & 12 {
+            0 => None,
+            4 => Some(chess::CastleSide::KingSide),
+            8 => Some(chess::CastleSide::QueenSide),
+            _ => return Err(Box::new(BadPacketError {}))
+        };
+        if flags[0] > 15 {
+            return Err(Box::new(BadPacketError {}));
+        }
+        Ok(chess::Move { from, to, promotion, capture: flags[0] & 1 != 0, castle, en_passant: flags[0] & 2 != 0 })
     }
 }
 
@@ -43,6 +119,18 @@ impl<T: Networkable> Networkable for (T, T) {
     }
 }
 
+impl Networkable for u64 {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.write_all(&self.to_be_bytes())?;
+        Ok(())
+    }
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let mut bytes = [0; 8];
+        stream.read_exact(&mut bytes)?;
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
 impl Networkable for usize {
     fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
         stream.write_all(&self.to_be_bytes())?;
@@ -55,23 +143,98 @@ impl Networkable for usize {
     }
 }
 
-/*impl Networkable for String {
-    fn serialize(&self, ster) -> Vec<u8> {
-        let mut vec = self.len().serialize();
-        vec.extend_from_slice(self.as_bytes());
-        vec
+// Milliseconds, as a u64.
+impl Networkable for Duration {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        (self.as_millis() as u64).serialize(stream)
+    }
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        Ok(Duration::from_millis(u64::deserialize(stream)?))
     }
-    fn deserialize(bytes: &[u8]) -> (Self, &[u8]) {
-        let (len, bytes) = usize::deserialize(bytes);
-        (from_utf8(&bytes[..len]).unwrap().to_string(), &bytes[len..])
+}
+
+// A time control goes over the wire as a byte for its kind, with 0 meaning no clock, then its base time
+// and its increment or delay.
+impl Networkable for Option<TimeControl> {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        let (kind, base, bonus) = match *self {
+            None => (0, Duration::ZERO, Duration::ZERO),
+            Some(TimeControl::SuddenDeath { base }) => (1, base, Duration::ZERO),
+            Some(TimeControl::Fischer { base, increment }) => (2, base, increment),
+            Some(TimeControl::Bronstein { base, delay }) => (3, base, delay)
+        };
+        stream.write_all(&[kind])?;
+        base.serialize(stream)?;
+        bonus.serialize(stream)
     }
-}*/
+
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let mut kind = [0; 1];
+        stream.read_exact(&mut kind)?;
+        let base = Duration::deserialize(stream)?;
+        let bonus = Duration::deserialize(stream)?;
+        match kind[0] {
+            0 => Ok(None),
+            1 => Ok(Some(TimeControl::SuddenDeath { base })),
+            2 => Ok(Some(TimeControl::Fischer { base, increment: bonus })),
+            3 => Ok(Some(TimeControl::Bronstein { base, delay: bonus })),
+            _ => Err(Box::new(BadPacketError {}))
+        }
+    }
+}
+
+// Longest string accepted from the other side, so a bad length can't make us allocate without end. Room
+// enough for a long game sent as a saved game.
+const MAX_STRING_BYTES: usize = 1 << 20;
+
+// A string goes over the wire as its length in bytes, then its UTF-8.
+impl Networkable for String {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        self.len().serialize(stream)?;
+        stream.write_all(self.as_bytes())?;
+        Ok(())
+    }
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let len = usize::deserialize(stream)?;
+        if len > MAX_STRING_BYTES {
+            return Err(Box::new(BadPacketError {}));
+        }
+        let mut bytes = vec![0; len];
+        stream.read_exact(&mut bytes)?;
+        Ok(from_utf8(&bytes)?.to_string())
+    }
+}
+
+// A variant goes over the wire as its place in chess::variant::VARIANTS.
+impl Networkable for chess::Variant {
+    fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        let index = chess::variant::VARIANTS.iter().position(|variant| variant == self).ok_or(BadPacketError {})?;
+        stream.write_all(&[index as u8])?;
+        Ok(())
+    }
+    fn deserialize(stream: &mut TcpStream) -> Result<Self, Box<dyn Error>> {
+        let mut buf = [0; 1];
+        stream.read_exact(&mut buf)?;
+        chess::variant::VARIANTS.get(buf[0] as usize).copied().ok_or_else(|| Box::new(BadPacketError {}) as Box<dyn Error>)
+    }
+}
 
 #[derive(Debug)]
 enum Packet {
-    Move(chess::BoardPosition, chess::BoardPosition),
-    AckMove,
-    RejMove
+    // With the mover's time left after the move, bonus included. Each side keeps its own clock and tells
+    // the other; without a clock it is zero and ignored.
+    Move(chess::Move, Duration),
+    // Carries the Zobrist hash of the position after the move, so the mover can check both sides agree.
+    AckMove(u64),
+    RejMove,
+    // Sent by the host as soon as the other side connects, to say what game they are playing: the variant,
+    // the starting position as FEN and the time control.
+    NewGame(chess::Variant, String, Option<TimeControl>),
+    // Sent by the host in place of NewGame when carrying on with a game that already has moves: the whole game
+    // as saved game JSON, so the other side gets the move history and clocks too.
+    ResumeGame(String),
+    // The sender's own flag has fallen, so it has lost on time.
+    Flag
 }
 
 #[derive(Debug, Clone)]
@@ -83,20 +246,54 @@ impl std::fmt::Display for BadPacketError {
 }
 impl std::error::Error for BadPacketError {}
 
+#[derive(Debug, Clone)]
+struct OutOfSyncError {}
+impl std::fmt::Display for OutOfSyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Peer's position does not match ours")
+    }
+}
+impl std::error::Error for OutOfSyncError {}
+
+#[derive(Debug, Clone)]
+struct RejectedMoveError {
+    mv: chess::Move
+}
+impl std::fmt::Display for RejectedMoveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Peer rejected our move {}", self.mv)
+    }
+}
+impl std::error::Error for RejectedMoveError {}
+
 impl Networkable for Packet {
     fn serialize(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
         println!("Serializing packet: {:?}", self);
         match self {
-            Packet::Move(bp1, bp2) => {
-                stream.write_all(&mut [0])?;
-                bp1.serialize(stream)?;
-                bp2.serialize(stream)?;
+            Packet::Move(mv, time_left) => {
+                stream.write_all(&[0])?;
+                mv.serialize(stream)?;
+                time_left.serialize(stream)?;
             },
-            Packet::AckMove => {
-                stream.write_all(&mut [1])?;
+            Packet::AckMove(hash) => {
+                stream.write_all(&[1])?;
+                hash.serialize(stream)?;
             },
             Packet::RejMove => {
-                stream.write_all(&mut [2])?;
+                stream.write_all(&[2])?;
+            },
+            Packet::NewGame(variant, fen, time_control) => {
+                stream.write_all(&[3])?;
+                variant.serialize(stream)?;
+                fen.serialize(stream)?;
+                time_control.serialize(stream)?;
+            },
+            Packet::Flag => {
+                stream.write_all(&[4])?;
+            },
+            Packet::ResumeGame(game) => {
+                stream.write_all(&[5])?;
+                game.serialize(stream)?;
             }
         }
         Ok(())
@@ -107,19 +304,37 @@ impl Networkable for Packet {
         stream.read_exact(&mut switch_byte)?;
         match switch_byte[0] {
             0 => {
-                let bp1 = chess::BoardPosition::deserialize(stream)?;
-                let bp2 = chess::BoardPosition::deserialize(stream)?;
-                println!("Deserialized packet: {:?}", Packet::Move(bp1, bp2));
-                Ok(Packet::Move(bp1, bp2))
+                let mv = chess::Move::deserialize(stream)?;
+                let time_left = Duration::deserialize(stream)?;
+                println!("Deserialized packet: {:?}", Packet::Move(mv, time_left));
+                Ok(Packet::Move(mv, time_left))
             },
             1 => {
-                println!("Deserialized packet: {:?}", Packet::AckMove);
-                Ok(Packet::AckMove)
+                let hash = u64::deserialize(stream)?;
+                println!("Deserialized packet: {:?}", Packet::AckMove(hash));
+                Ok(Packet::AckMove(hash))
             },
             2 => {
                 println!("Deserialized packet: {:?}", Packet::RejMove);
                 Ok(Packet::RejMove)
             },
+            3 => {
+                let variant = chess::Variant::deserialize(stream)?;
+                let fen = String::deserialize(stream)?;
+                let time_control = Option::<TimeControl>::deserialize(stream)?;
+                let packet = Packet::NewGame(variant, fen, time_control);
+                println!("Deserialized packet: {:?}", packet);
+                Ok(packet)
+            },
+            4 => {
+                println!("Deserialized packet: {:?}", Packet::Flag);
+                Ok(Packet::Flag)
+            },
+            5 => {
+                let packet = Packet::ResumeGame(String::deserialize(stream)?);
+                println!("Deserialized packet: {:?}", packet);
+                Ok(packet)
+            },
             _ => {
                 println!("Deserialized error packet!!!");
                 Err(Box::new(BadPacketError {}))
@@ -128,7 +343,8 @@ impl Networkable for Packet {
     }
 }
 
-type StateResult = Result<Box<dyn ChessState>, Box<dyn Error>>;
+// Ok(None) means the game is over and there is no next state.
+type StateResult = Result<Option<Box<dyn ChessState>>, Box<dyn Error>>;
 
 pub trait ChessState {
     fn next(&mut self) -> StateResult;
@@ -139,59 +355,151 @@ pub struct GlobalState {
     window: Window, 
     surface: ImageSurface,
     stream: TcpStream,
-    width: usize,
-    height: usize
+    // The side this end plays: the host is White
+    color: chess::Color,
+    // The game so far, written out to files after every move.
+    record: chess::pgn::PgnGame,
+    files: GameFiles
 }
 
-use std::time;
-const ONE_MILLI : time::Duration = time::Duration::from_millis(20);
+// Where to keep a copy of the game as it goes, if anywhere: as PGN, and as a saved game to resume from.
+#[derive(Clone, Debug, Default)]
+pub struct GameFiles {
+    pub pgn: Option<String>,
+    pub save: Option<String>
+}
 
 impl GlobalState {
-    fn click_to_board(&self, pos: chess::ScreenPosition) -> Result<chess::BoardPosition, ()> {
-        let bp = chess::BoardPosition((pos.0 * 8.0 / (self.width as f64)) as usize, (pos.1 * 8.0 / (self.height as f64)) as usize);
-        if bp.0 < 8 && bp.1 < 8 {
-            Ok(bp)
-        } else {
-            Err(())
+    // Let the board act on each key pressed since the window was last updated. Whether anything changed.
+    fn handle_keys(&mut self) -> bool {
+        window::handle_keys(&self.window, &mut self.board)
+    }
+
+    // Wait for a click on the board or a piece to be dragged and dropped. None if our flag falls first: we are
+    // only asked on our own move, so ours is the only clock running.
+    fn get_next_gesture(&mut self) -> Result<Option<chess::Gesture>, Box<dyn Error>> {
+        window::next_gesture(&mut self.window, &mut self.surface, &mut self.board)
+    }
+
+    // Let the window handle its events, redrawing as the clocks count down.
+    fn tick(&mut self) -> Result<(), Box<dyn Error>> {
+        window::tick(&mut self.window, &mut self.surface, &mut self.board)
+    }
+
+    fn our_flag_fell(&self) -> bool {
+        self.board.flagged() == Some(self.color)
+    }
+
+    // Wait for the next packet from the other side, keeping the window and clocks going meanwhile.
+    fn next_packet(&mut self) -> Result<Packet, Box<dyn Error>> {
+        self.stream.set_read_timeout(Some(window::FRAME_TIME))?;
+        loop {
+            match self.stream.peek(&mut [0]) {
+                Ok(0) => return Err("The other side closed the connection".into()),
+                Ok(_) => break,
+                Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => self.tick()?,
+                Err(err) => return Err(Box::new(err))
+            }
         }
+        // The rest of the packet is on its way, so it is safe to block for it
+        self.stream.set_read_timeout(None)?;
+        Packet::deserialize(&mut self.stream)
     }
     
-    fn get_next_legal_click(&mut self) -> chess::BoardPosition {
-        self.window.limit_update_rate(Some(ONE_MILLI));
+    // Open the promotion picker on to_pos and wait for a click on one of its pieces. None if our flag falls first.
+    fn get_promotion_choice(&mut self, to_pos: chess::Square) -> Result<Option<chess::Piece>, Box<dyn Error>> {
+        let color = self.board.game_state.turn;
+        self.board.promotion_picker = Some((to_pos, color));
+        self.draw()?;
+        let piece = loop {
+            match self.get_next_gesture()? {
+                Some(chess::Gesture::Click(bp)) => if let Some(piece) = self.board.promotion_picker_choice(bp) {
+                    break Some(piece);
+                },
+                Some(chess::Gesture::Drop(..)) => {},
+                None => break None
+            }
+        };
+        self.board.promotion_picker = None;
+        self.draw()?;
+        Ok(piece)
+    }
+
+    // Our move, picked by clicking its start and end squares or by dragging the piece from one to the other. None
+    // if our flag falls first.
+    fn choose_move(&mut self) -> Result<Option<chess::Move>, Box<dyn Error>> {
         loop {
-            while !self.window.get_mouse_down(MouseButton::Left) {self.window.update();/*draw(window, surface, board);*/}
-            println!("Got down event");
-            while self.window.get_mouse_down(MouseButton::Left) {self.window.update();/*draw(window, surface, board);*/}
-            println!("Got up event");
-            let opt_pos = self.window.get_mouse_pos(MouseMode::Clamp);
-            if let Some(pos) = opt_pos {
-                match self.click_to_board(chess::ScreenPosition(pos.0 as f64, pos.1 as f64)) {
-                    Ok(bp) => return bp,
-                    Err(()) => continue
+            let (bp1, bp2) = match self.get_next_gesture()? {
+                None => return Ok(None),
+                Some(chess::Gesture::Drop(from, Some(to))) => (from, to),
+                // Dropped off the board, so the piece just goes back
+                Some(chess::Gesture::Drop(_, None)) => continue,
+                Some(chess::Gesture::Click(bp1)) => {
+                    // Assure we are clicking the correct color
+                    if !self.board.game_state.is_legal_start(bp1) {
+                        continue;
+                    }
+                    // Highlight clicked square and draw in w/ highlight
+                    self.board.highlight = Some(bp1);
+                    self.draw()?;
+                    // Get next click and delete highlight + draw (whether or not move is allowed)
+                    let gesture = self.get_next_gesture()?;
+                    self.board.highlight = None;
+                    self.draw()?;
+                    match gesture {
+                        None => return Ok(None),
+                        Some(chess::Gesture::Click(bp2)) => (bp1, bp2),
+                        // Dragging a piece instead tries that move
+                        Some(chess::Gesture::Drop(from, Some(to))) => (from, to),
+                        Some(chess::Gesture::Drop(_, None)) => continue,
+                    }
+                }
+            };
+            // If move is legal, return it, otherwise keep looping. A pawn reaching the last rank has a move for each promotion piece.
+            let candidates: Vec<chess::Move> = self.board.game_state.legal_moves_from(bp1).into_iter().filter(|mv| mv.to == bp2).collect();
+            if let Some(first) = candidates.first() {
+                let promotion = if first.promotion.is_some() {
+                    let Some(piece) = self.get_promotion_choice(bp2)? else { return Ok(None) };
+                    Some(piece)
+                } else {
+                    None
+                };
+                if let Some(mv) = candidates.into_iter().find(|mv| mv.promotion == promotion) {
+                    return Ok(Some(mv));
                 }
             }
         }
     }
-    
-    fn draw(&mut self) -> Result<(), Box<dyn Error>> {
-        let size = self.window.get_size();
-        {
-            let mut context = Context::new(&self.surface)?;
-            
-            context.set_source_rgb(1.0, 1.0, 1.0);
-            context.paint()?;
-        
-            self.board.draw(&mut context, chess::ScreenPosition(0.0, 0.0))?;
+
+    // Play a move that both sides have agreed on, and keep the saved game up to date.
+    fn play_move(&mut self, mv: chess::Move) -> Result<(), Box<dyn Error>> {
+        self.record.push_move(mv)?;
+        self.board.game_state.do_move(mv);
+        self.record.set_outcome(self.board.outcome());
+        self.save_record()
+    }
+
+    fn save_record(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.files.pgn {
+            std::fs::write(path, self.record.to_pgn())?;
+        }
+        if let Some(path) = &self.files.save {
+            SavedGame { tags: self.record.tags.clone(), state: self.board.game_state.clone(), clock: self.board.clock.clone() }.save(path)?;
         }
-        let data = self.surface.data();
-        let uwdata = data.unwrap();
-        let data = unsafe {
-            let (_, d, _) = uwdata.align_to::<u32>();
-            d
-        };
-        self.window.update_with_buffer(&data, size.0, size.1)?;
         Ok(())
     }
+
+    // The game ends with flagged out of time.
+    fn flag_fell(&mut self, flagged: chess::Color) -> Result<chess::GameOutcome, Box<dyn Error>> {
+        let outcome = self.board.game_state.flag_fall_outcome(flagged);
+        self.record.set_outcome(Some(outcome));
+        self.save_record()?;
+        Ok(outcome)
+    }
+
+    fn draw(&mut self) -> Result<(), Box<dyn Error>> {
+        window::draw(&mut self.window, &mut self.surface, &self.board)
+    }
 }
 
 use std::rc::Rc;
@@ -205,57 +513,65 @@ struct OtherMove {
 }
 struct AwaitAck {
     global_state: Rc<RefCell<GlobalState>>,
-    next_move: (chess::BoardPosition, chess::BoardPosition)
+    next_move: chess::Move,
+    time_left: Duration
+}
+struct GameOver {
+    global_state: Rc<RefCell<GlobalState>>,
+    outcome: chess::GameOutcome
+}
+
+// The state to enter once a move has been played: the game may have just ended.
+fn after_move(global_state: &Rc<RefCell<GlobalState>>, next: Box<dyn ChessState>) -> StateResult {
+    match global_state.borrow().board.outcome() {
+        Some(outcome) => Ok(Some(Box::new(GameOver{global_state: global_state.clone(), outcome}))),
+        None => Ok(Some(next))
+    }
 }
 
-use chess::Drawable;
 
 impl ChessState for MyMove {
     fn next(&mut self) -> StateResult {
         let mut gs = self.global_state.borrow_mut();
         gs.draw()?;
-        let mut bp1;
-        let mut bp2;
-        loop {
-            // Get a click on board and assure we are clicking the correct color
-            bp1 = gs.get_next_legal_click();
-            if !gs.board.game_state.is_legal_start(bp1) {
-                continue;
-            }
-            // Highlight clicked square and draw in w/ highlight
-            gs.board.highlight = Some(bp1);
-            gs.draw()?;
-            // Get next click and delete highlight + draw (whether or not move is allowed)
-            bp2 = gs.get_next_legal_click();
-            gs.board.highlight = None;
-            gs.draw()?;
-            // If move is legal, break, otherwise keep looping
-            if gs.board.game_state.is_legal(bp1, bp2) {
-                break;
+        let chosen = gs.choose_move()?;
+        // A move made after the flag fell doesn't count
+        let next_move = match chosen {
+            Some(mv) if !gs.our_flag_fell() => mv,
+            _ => {
+                Packet::Flag.serialize(&mut gs.stream)?;
+                let color = gs.color;
+                let outcome = gs.flag_fell(color)?;
+                return Ok(Some(Box::new(GameOver{global_state: self.global_state.clone(), outcome})));
             }
-        }
-        // We have a legal move bp1, bp2 -- Transition to the AwaitAck state
-        Ok(Box::new(AwaitAck{global_state: self.global_state.clone(), next_move: (bp1, bp2)}))
+        };
+        let time_left = gs.board.clock.as_mut().map_or(Duration::ZERO, |clock| clock.press(Instant::now()));
+        // We have a legal move -- Transition to the AwaitAck state
+        Ok(Some(Box::new(AwaitAck{global_state: self.global_state.clone(), next_move, time_left})))
     }
 }
 
 impl ChessState for AwaitAck {
     fn next(&mut self) -> StateResult {
         let mut gs = self.global_state.borrow_mut();        
-        Packet::Move(self.next_move.0, self.next_move.1).serialize(&mut gs.stream)?;
+        Packet::Move(self.next_move, self.time_left).serialize(&mut gs.stream)?;
         loop {
             gs.draw()?;
-            let next_packet = Packet::deserialize(&mut gs.stream)?;
+            let next_packet = gs.next_packet()?;
             match next_packet {
-                Packet::AckMove => {
+                Packet::AckMove(hash) => {
                     // Success case -- they acknowledged our move, so we can do the move and move into new state
-                    gs.board.game_state.do_move(self.next_move.0, self.next_move.1);
+                    gs.play_move(self.next_move)?;
+                    if hash != gs.board.game_state.zobrist_hash() {
+                        return Err(Box::new(OutOfSyncError {}));
+                    }
                     gs.draw()?;
-                    return Ok(Box::new(OtherMove{global_state: self.global_state.clone()}));
+                    drop(gs);
+                    return after_move(&self.global_state, Box::new(OtherMove{global_state: self.global_state.clone()}));
                 },
                 Packet::RejMove => {
-                    // Failure case -- Move was rejected. :( lets just die because this shouldn't happen
-                    panic!("Move was rejected!!!!");
+                    // Failure case -- Move was rejected, so the two sides disagree about the game
+                    return Err(Box::new(RejectedMoveError { mv: self.next_move }));
                 },
                 _ => {}
             }
@@ -269,61 +585,172 @@ impl ChessState for OtherMove {
         // Wait to receive other's move.
         loop {
             gs.draw()?;
-            let next_packet = Packet::deserialize(&mut gs.stream)?;
+            let next_packet = gs.next_packet()?;
             match next_packet {
-                Packet::Move(bp1, bp2) => {
-                    // Check legality of move.
-                    if gs.board.game_state.is_legal(bp1, bp2) {
-                        // Accept move, draw board, go to MyMove state
-                        Packet::AckMove.serialize(&mut gs.stream)?;
-                        gs.board.game_state.do_move(bp1, bp2);
-                        gs.draw()?;
-                        return Ok(Box::new(MyMove{global_state: self.global_state.clone()}))
-                    } else {
-                        // Reject move and keep waiting
-                        Packet::RejMove.serialize(&mut gs.stream)?;
+                Packet::Move(mv, time_left) => {
+                    // Check legality of move, flags included
+                    match gs.board.game_state.check_move(mv) {
+                        Ok(()) => {
+                            // Accept move, draw board, go to MyMove state. The mover's own count of its time wins over ours.
+                            gs.play_move(mv)?;
+                            let mover = !gs.board.game_state.turn;
+                            if let Some(clock) = gs.board.clock.as_mut() {
+                                clock.press(Instant::now());
+                                clock.set_remaining(mover, time_left);
+                            }
+                            Packet::AckMove(gs.board.game_state.zobrist_hash()).serialize(&mut gs.stream)?;
+                            gs.draw()?;
+                            drop(gs);
+                            return after_move(&self.global_state, Box::new(MyMove{global_state: self.global_state.clone()}));
+                        },
+                        Err(reason) => {
+                            // Reject move and keep waiting
+                            println!("Rejected move {}: {}", mv, reason);
+                            Packet::RejMove.serialize(&mut gs.stream)?;
+                        }
                     }
                 },
+                Packet::Flag => {
+                    let opponent = !gs.color;
+                    let outcome = gs.flag_fell(opponent)?;
+                    return Ok(Some(Box::new(GameOver{global_state: self.global_state.clone(), outcome})));
+                },
                 _ => {}
             }
         }
     }
 }
 
-pub fn run_server(board: chess::Board,
+impl ChessState for GameOver {
+    fn next(&mut self) -> StateResult {
+        let mut gs = self.global_state.borrow_mut();
+        println!("{}", self.outcome);
+        if let Some(clock) = gs.board.clock.as_mut() {
+            clock.stop(Instant::now());
+        }
+        // Leave the final position up until the window is closed.
+        while gs.window.is_open() {
+            gs.handle_keys();
+            gs.draw()?;
+        }
+        Ok(None)
+    }
+}
+
+// The record of a network game carrying on from game, tagged as such.
+fn network_record(game: &SavedGame, port: usize) -> Result<chess::pgn::PgnGame, chess::ChessError> {
+    let mut record = game.record()?;
+    record.set_tag("Event", "Network game");
+    record.set_tag("Site", &format!("127.0.0.1:{}", port));
+    if let Some(clock) = &game.clock {
+        record.set_tag("TimeControl", &clock.control.to_string());
+    }
+    Ok(record)
+}
+
+// Start the side to move's clock and play until the game is over, starting with whichever side is to move.
+fn play(mut global_state: GlobalState) -> Result<(), Box<dyn Error>> {
+    // Each side sees the board from its own side to begin with
+    global_state.board.orientation = global_state.color;
+    let turn = global_state.board.game_state.turn;
+    if let Some(clock) = global_state.board.clock.as_mut() {
+        clock.start(turn, Instant::now());
+    }
+    global_state.draw()?;
+    let our_move = turn == global_state.color;
+    let global_state = Rc::new(RefCell::new(global_state));
+    let first: Box<dyn ChessState> = if our_move {
+        Box::new(MyMove{global_state: global_state.clone()})
+    } else {
+        Box::new(OtherMove{global_state: global_state.clone()})
+    };
+    // A resumed game may already be over
+    let mut run_state = after_move(&global_state, first)?;
+    while let Some(mut state) = run_state {
+        run_state = state.next()?;
+    }
+    Ok(())
+}
+
+// Host a game as White on board carrying on from game, which is a new game if it has no moves yet. The other side is
+// sent the variant, position and time control, or the whole game when resuming, and the clock starts as soon
+// as they connect.
+pub fn run_server(mut board: chess::Board,
+    game: SavedGame,
     window: Window, 
     surface: ImageSurface,
     port: usize,
-    width: usize,
-    height: usize) -> Result<(), Box<dyn Error>> {
+    files: GameFiles) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(format!("127.0.0.1:{}", port))?;
 
-    let stream = listener.incoming().next().unwrap()?;
-
-    let mut global_state = GlobalState {board, window, surface, stream, width, height};
-    global_state.draw()?;
-    
-    let mut run_state : Box<dyn ChessState> = Box::new(MyMove{global_state: Rc::new(RefCell::new(global_state))});
-    loop {
-        run_state = run_state.next()?;
+    let mut stream = listener.incoming().next().unwrap()?;
+    if game.state.move_history().is_empty() {
+        Packet::NewGame(game.state.variant(), game.state.to_fen(), game.clock.as_ref().map(|clock| clock.control)).serialize(&mut stream)?;
+    } else {
+        Packet::ResumeGame(game.to_json()).serialize(&mut stream)?;
     }
-    Ok(())
+
+    let record = network_record(&game, port)?;
+    board.game_state = game.state;
+    board.clock = game.clock;
+    play(GlobalState {board, window, surface, stream, color: chess::Color::White, record, files})
 }
 
-pub fn run_client(board: chess::Board,
+// Join a game as Black on board, with whatever variant, position and time control the host sends, or the game it
+// is resuming.
+pub fn run_client(mut board: chess::Board,
     window: Window, 
     surface: ImageSurface,
     port: usize,
-    width: usize,
-    height: usize) -> Result<(), Box<dyn Error>> {
-    let stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+    files: GameFiles) -> Result<(), Box<dyn Error>> {
+    let mut stream = TcpStream::connect(format!("127.0.0.1:{}", port))?;
+    let game = match Packet::deserialize(&mut stream)? {
+        Packet::NewGame(variant, fen, time_control) => {
+            let mut game = SavedGame::new(chess::GameState::from_variant_fen(variant, &fen)?);
+            game.clock = time_control.map(Clock::new);
+            game
+        },
+        Packet::ResumeGame(json) => SavedGame::from_json(&json)?,
+        _ => return Err(Box::new(BadPacketError {}))
+    };
 
-    let mut global_state = GlobalState {board, window, surface, stream, width, height};
-    global_state.draw()?;
-    
-    let mut run_state : Box<dyn ChessState> = Box::new(OtherMove{global_state: Rc::new(RefCell::new(global_state))});
-    loop {
-        run_state = run_state.next()?;
+    let record = network_record(&game, port)?;
+    board.game_state = game.state;
+    board.clock = game.clock;
+    play(GlobalState {board, window, surface, stream, color: chess::Color::Black, record, files})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_packets() -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut sender = TcpStream::connect(listener.local_addr()?)?;
+        let mut receiver = listener.incoming().next().unwrap()?;
+        let state = chess::GameState::from_fen(chess::STARTING_FEN)?;
+        let mv = state.parse_uci("e2e4")?;
+        for time_control in ["300", "180+2", "60d0.5"] {
+            Packet::NewGame(chess::Variant::Standard, chess::STARTING_FEN.to_string(), Some(time_control.parse()?)).serialize(&mut sender)?;
+            match Packet::deserialize(&mut receiver)? {
+                Packet::NewGame(_, _, Some(received)) => assert_eq!(received.to_string(), time_control),
+                packet => panic!("got {:?}", packet)
+            }
+        }
+        let fen = chess::variant::chess960_fen(0);
+        Packet::NewGame(chess::Variant::Chess960, fen.clone(), None).serialize(&mut sender)?;
+        Packet::Move(mv, Duration::from_millis(61_250)).serialize(&mut sender)?;
+        Packet::Flag.serialize(&mut sender)?;
+        assert!(matches!(Packet::deserialize(&mut receiver)?, Packet::NewGame(chess::Variant::Chess960, received, None) if received == fen));
+        assert!(matches!(Packet::deserialize(&mut receiver)?, Packet::Move(received, left) if received == mv && left == Duration::from_millis(61_250)));
+        assert!(matches!(Packet::deserialize(&mut receiver)?, Packet::Flag));
+        let mut resumed = state.clone();
+        resumed.do_move(mv);
+        Packet::ResumeGame(SavedGame::new(resumed.clone()).to_json()).serialize(&mut sender)?;
+        match Packet::deserialize(&mut receiver)? {
+            Packet::ResumeGame(json) => assert_eq!(SavedGame::from_json(&json)?.state.move_history(), resumed.move_history()),
+            packet => panic!("got {:?}", packet)
+        }
+        Ok(())
     }
-    Ok(())
-}
\ No newline at end of file
+}
@@ -1,93 +1,127 @@
-use minifb::{MouseMode, MouseButton, Window, WindowOptions};
+use minifb::{Window, WindowOptions};
 extern crate cairo;
-use cairo::{ ImageSurface, Format, Context };
-use std::{thread, time};
-
-const ONE_MILLI : time::Duration = time::Duration::from_millis(20);
+use cairo::{ ImageSurface, Format };
+use std::time::Instant;
 
 use std::error::Error;
 //use font_kit::loaders;
 const WIDTH: usize = 400;
 const HEIGHT: usize = 400;
-#[path = "chess.rs"] mod chess;
-use chess::Drawable;
-#[path = "net_chess.rs"] mod net_chess;
-
-fn click_to_board(pos: chess::ScreenPosition) -> Result<chess::BoardPosition, ()> {
-    let bp = chess::BoardPosition((pos.0 * 8.0 / (WIDTH as f64)) as usize, (pos.1 * 8.0 / (HEIGHT as f64)) as usize);
-    if bp.0 < 8 && bp.1 < 8 {
-        Ok(bp)
-    } else {
-        Err(())
-    }
-}
-
-fn get_next_legal_click(window: &mut Window, surface: &mut ImageSurface, board: &chess::Board) -> chess::BoardPosition {
-    window.limit_update_rate(Some(ONE_MILLI));
-    loop {
-        while !window.get_mouse_down(MouseButton::Left) {window.update();/*draw(window, surface, board);*/}
-        println!("Got down event");
-        while window.get_mouse_down(MouseButton::Left) {window.update();/*draw(window, surface, board);*/}
-        println!("Got up event");
-        let opt_pos = window.get_mouse_pos(MouseMode::Clamp);
-        if let Some(pos) = opt_pos {
-            match click_to_board(chess::ScreenPosition(pos.0 as f64, pos.1 as f64)) {
-                Ok(bp) => return bp,
-                Err(()) => continue
-            }
-        }
-    }
-}
-
-fn draw(window: &mut Window, surface: &mut ImageSurface, board: &chess::Board) -> Result<(), Box<dyn Error>> {
-    let size = window.get_size();
-    {
-        let mut context = Context::new(surface)?;
-        
-        context.set_source_rgb(1.0, 1.0, 1.0);
-        context.paint()?;
-    
-        board.draw(&mut context, chess::ScreenPosition(0.0, 0.0))?;
-    }
-    let data = surface.data();
-    let uwdata = data.unwrap();
-    let data = unsafe {
-        let (_, d, _) = uwdata.align_to::<u32>();
-        d
-    };
-    window.update_with_buffer(&data, size.0, size.1)?;
-    Ok(())
-}
+use my_project::chess;
+use chess::assets::Assets;
+use chess::save::SavedGame;
+use chess::window::{draw, handle_keys, next_gesture};
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let mut window = Window::new("Raqote", WIDTH, HEIGHT, WindowOptions {
+// Hotseat game on one window, carrying on from game: a new game just hasn't had any moves yet. If pgn_path is
+// given the game is written there as PGN after every move, and if save_path is given it is saved there to be
+// resumed later. The pieces are drawn with the images found through assets. With a clock, the side to move's
+// time starts straight away.
+pub fn main(pgn_path: Option<&str>, save_path: Option<&str>, game: SavedGame, assets: &Assets) -> Result<(), Box<dyn Error>> {
+    let mut window = Window::new("Raqote", WIDTH + chess::SIDE_PANEL_WIDTH as usize, HEIGHT, WindowOptions {
         ..WindowOptions::default()
     }).unwrap();
     let size = window.get_size();
     let mut surface = ImageSurface::create(Format::ARgb32, size.0 as i32, size.1 as i32)
         .expect("Couldn’t create surface");
-    let mut board = chess::Board::new(WIDTH as f64, HEIGHT as f64);
-    board.setup_new_game();
+    let mut board = chess::Board::with_assets(WIDTH as f64, HEIGHT as f64, assets)?;
+    let mut record = game.record()?;
+    record.set_tag("Event", "Hotseat game");
+    board.game_state = game.state;
+    board.clock = game.clock;
+    if let Some(clock) = board.clock.as_mut() {
+        record.set_tag("TimeControl", &clock.control.to_string());
+        clock.start(board.game_state.turn, Instant::now());
+    }
+    let save = |record: &chess::pgn::PgnGame, board: &chess::Board| -> Result<(), Box<dyn Error>> {
+        if let Some(path) = pgn_path {
+            std::fs::write(path, record.to_pgn())?;
+        }
+        if let Some(path) = save_path {
+            SavedGame { tags: record.tags.clone(), state: board.game_state.clone(), clock: board.clock.clone() }.save(path)?;
+        }
+        Ok(())
+    };
     draw(&mut window, &mut surface, &board)?;
     draw(&mut window, &mut surface, &board)?;
-    loop {
+    let outcome = loop {
+        // A resumed game may already be over
+        if let Some(outcome) = board.outcome() {
+            break Some(outcome);
+        }
         draw(&mut window, &mut surface, &board)?;
         draw(&mut window, &mut surface, &board)?;
-        let bp1 = get_next_legal_click(&mut window, &mut surface, &board);
-        if !board.game_state.is_legal_start(bp1) {
-            continue;
+        // A piece dropped on a square, or clicked on and then the square clicked
+        let (bp1, bp2) = match next_gesture(&mut window, &mut surface, &mut board)? {
+            None => break None,
+            Some(chess::Gesture::Drop(from, Some(to))) => (from, to),
+            Some(chess::Gesture::Drop(_, None)) => continue,
+            Some(chess::Gesture::Click(bp1)) => {
+                if !board.game_state.is_legal_start(bp1) {
+                    continue;
+                }
+                board.highlight = Some(bp1);
+                draw(&mut window, &mut surface, &board)?;
+                let gesture = next_gesture(&mut window, &mut surface, &mut board)?;
+                board.highlight = None;
+                match gesture {
+                    None => break None,
+                    Some(chess::Gesture::Click(bp2)) => (bp1, bp2),
+                    // Dragging a piece instead tries that move
+                    Some(chess::Gesture::Drop(from, Some(to))) => (from, to),
+                    Some(chess::Gesture::Drop(_, None)) => continue,
+                }
+            }
+        };
+        // A pawn reaching the last rank has a move for each promotion piece
+        let candidates: Vec<chess::Move> = board.game_state.legal_moves_from(bp1).into_iter().filter(|mv| mv.to == bp2).collect();
+        if let Some(first) = candidates.first() {
+            let mut promotion = None;
+            if first.promotion.is_some() {
+                board.promotion_picker = Some((bp2, board.game_state.turn));
+                draw(&mut window, &mut surface, &board)?;
+                while promotion.is_none() {
+                    match next_gesture(&mut window, &mut surface, &mut board)? {
+                        Some(chess::Gesture::Click(bp)) => promotion = board.promotion_picker_choice(bp),
+                        Some(chess::Gesture::Drop(..)) => {},
+                        None => break
+                    }
+                }
+                board.promotion_picker = None;
+            }
+            // A move made after the flag fell doesn't count
+            if board.flagged().is_some() {
+                break None;
+            }
+            if let Some(mv) = candidates.into_iter().find(|mv| mv.promotion == promotion) {
+                record.push_move(mv)?;
+                board.game_state.do_move(mv);
+                if let Some(clock) = board.clock.as_mut() {
+                    clock.press(Instant::now());
+                }
+                record.set_outcome(board.outcome());
+                save(&record, &board)?;
+            }
         }
-        board.highlight = Some(bp1);
         draw(&mut window, &mut surface, &board)?;
-        let bp2 = get_next_legal_click(&mut window, &mut surface, &board);
-        board.highlight = None;
-        if board.game_state.is_legal(bp1, bp2) {
-            board.game_state.do_move(bp1, bp2);
-        }
-        if board.is_checkmated() {
-            break;
+    };
+    // Out of the loop without an outcome means the side to move ran out of time
+    let outcome = match outcome {
+        Some(outcome) => outcome,
+        None => {
+            let outcome = board.game_state.flag_fall_outcome(board.game_state.turn);
+            record.set_outcome(Some(outcome));
+            save(&record, &board)?;
+            outcome
         }
+    };
+    if let Some(clock) = board.clock.as_mut() {
+        clock.stop(Instant::now());
+    }
+    println!("{}", outcome);
+    // Leave the final position up until the window is closed.
+    while window.is_open() {
+        handle_keys(&window, &mut board);
         draw(&mut window, &mut surface, &board)?;
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,374 @@
+// The Universal Chess Interface: lets chess GUIs and tournament managers drive the engine over stdin and stdout.
+// Searches run on their own thread so that stop and isready are answered while the engine thinks.
+use my_project::chess::{GameState, Variant};
+use my_project::chess::engine::{Engine, SearchLimits, SearchResult, DEFAULT_HASH_MEGABYTES};
+use my_project::chess::polyglot::Book;
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Output = Arc<Mutex<dyn Write + Send>>;
+
+fn send(output: &Output, line: &str) -> io::Result<()> {
+    // A search thread that panicked mid-line leaves nothing worth protecting in the writer
+    let mut output = output.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    writeln!(output, "{}", line)?;
+    output.flush()
+}
+
+// A search running on its own thread, which hands the engine back when it ends.
+struct Search {
+    handle: JoinHandle<Engine>,
+    infinite: bool,
+}
+
+struct Session {
+    position: GameState,
+    // None while a search has it
+    engine: Option<Engine>,
+    search: Option<Search>,
+    stop: Arc<AtomicBool>,
+    output: Output,
+    // The engine plays from the book at book_file only once the GUI also turns on OwnBook
+    own_book: bool,
+    book_file: String,
+    // Rules positions are set up under, from UCI_Variant or UCI_Chess960
+    variant: Variant,
+}
+
+// Speak UCI on input and output until quit or the end of input. A search still running at the end of input
+// is allowed to finish, unless it is an infinite one, so a scripted session gets its answer.
+pub fn run(input: impl BufRead, output: impl Write + Send + 'static) -> Result<(), Box<dyn Error>> {
+    let engine = Engine::new();
+    let mut session = Session {
+        position: GameState::from_fen(Variant::Standard.starting_fen())?,
+        stop: engine.stop_handle(),
+        engine: Some(engine),
+        search: None,
+        output: Arc::new(Mutex::new(output)),
+        own_book: false,
+        book_file: String::new(),
+        variant: Variant::Standard,
+    };
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue
+        };
+        match command {
+            "uci" => {
+                session.send("id name my-project")?;
+                session.send("id author my-project developers")?;
+                session.send(&format!("option name Hash type spin default {} min 1 max 4096", DEFAULT_HASH_MEGABYTES))?;
+                session.send("option name Clear Hash type button")?;
+                session.send("option name OwnBook type check default false")?;
+                session.send("option name Book File type string default <empty>")?;
+                session.send("option name UCI_Chess960 type check default false")?;
+                session.send("option name UCI_Variant type combo default chess var chess var chess960 var kingofthehill var threecheck var atomic var antichess")?;
+                session.send("uciok")?;
+            },
+            "isready" => session.send("readyok")?,
+            "ucinewgame" => {
+                session.engine()?.clear();
+                session.position = GameState::from_variant_fen(session.variant, session.variant.starting_fen())?;
+            },
+            "setoption" => session.set_option(args)?,
+            "position" => {
+                // A position that doesn't load leaves the last good one in place
+                if let Err(err) = session.set_position(args) {
+                    session.send(&format!("info string Bad position: {}", err))?;
+                }
+            },
+            "go" => session.go(args)?,
+            "stop" => session.finish_search(true)?,
+            "quit" => return session.finish_search(true),
+            _ => session.send(&format!("info string Unknown command '{}'", command))?
+        }
+    }
+    session.finish_search(false)
+}
+
+impl Session {
+    fn send(&self, line: &str) -> io::Result<()> {
+        send(&self.output, line)
+    }
+
+    // Wait for the running search, if any, and take the engine back. With stop it is told to finish now.
+    fn finish_search(&mut self, stop: bool) -> Result<(), Box<dyn Error>> {
+        if let Some(search) = self.search.take() {
+            if stop || search.infinite {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+            let engine = search.handle.join().map_err(|_| "The search thread panicked")?;
+            self.engine = Some(engine);
+        }
+        Ok(())
+    }
+
+    // The engine, stopping any search that is using it.
+    fn engine(&mut self) -> Result<&mut Engine, Box<dyn Error>> {
+        self.finish_search(true)?;
+        Ok(self.engine.as_mut().ok_or("The engine went missing")?)
+    }
+
+    // setoption name <id> [value <x>]
+    fn set_option(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let value_at = args.iter().position(|&word| word == "value").unwrap_or(args.len());
+        let name = args.get(1..value_at).unwrap_or(&[]).join(" ");
+        let value = args.get(value_at + 1..).unwrap_or(&[]).join(" ");
+        match name.to_ascii_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(megabytes) if megabytes > 0 => self.engine()?.set_hash_size(megabytes),
+                _ => self.send(&format!("info string Bad Hash size '{}'", value))?
+            },
+            "clear hash" => self.engine()?.clear(),
+            "ownbook" => {
+                self.own_book = value == "true";
+                self.load_book()?;
+            },
+            "book file" => {
+                self.book_file = if value == "<empty>" {String::new()} else {value};
+                self.load_book()?;
+            },
+            // Turning Chess960 off leaves any other variant the GUI picked alone
+            "uci_chess960" => if value == "true" {
+                self.variant = Variant::Chess960;
+            } else if self.variant == Variant::Chess960 {
+                self.variant = Variant::Standard;
+            },
+            "uci_variant" => match value.parse() {
+                Ok(variant) => self.variant = variant,
+                Err(err) => self.send(&format!("info string {}", err))?
+            },
+            _ => self.send(&format!("info string Unknown option '{}'", name))?
+        }
+        Ok(())
+    }
+
+    // Hand the engine the book the options ask for, or none. A book that can't be read is reported and left out.
+    fn load_book(&mut self) -> Result<(), Box<dyn Error>> {
+        let book = match (self.own_book, self.book_file.is_empty()) {
+            (true, false) => match Book::open(&self.book_file) {
+                Ok(book) => Some(book),
+                Err(err) => {
+                    self.send(&format!("info string Can't read book '{}': {}", self.book_file, err))?;
+                    None
+                }
+            },
+            _ => None
+        };
+        self.engine()?.set_book(book);
+        Ok(())
+    }
+
+    // position (startpos | fen <fen>) [moves <move>...]
+    fn set_position(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let moves_at = args.iter().position(|&word| word == "moves").unwrap_or(args.len());
+        let mut position = match args.first() {
+            Some(&"startpos") => GameState::from_variant_fen(self.variant, self.variant.starting_fen())?,
+            Some(&"fen") => GameState::from_variant_fen(self.variant, &args[1..moves_at].join(" "))?,
+            _ => return Err("expected startpos or fen".into())
+        };
+        for uci in args.iter().skip(moves_at + 1) {
+            let mv = position.parse_uci(uci)?;
+            position.do_move(mv);
+        }
+        self.position = position;
+        Ok(())
+    }
+
+    // go [depth <plies>] [nodes <n>] [movetime <ms>] [wtime <ms>] [btime <ms>] [winc <ms>] [binc <ms>] [movestogo <n>] [infinite]
+    fn go(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        let mut engine = match self.engine.take() {
+            Some(engine) => engine,
+            None => {
+                self.finish_search(true)?;
+                self.engine.take().ok_or("The engine went missing")?
+            }
+        };
+        let number = |i: usize| args.get(i + 1).and_then(|word| word.parse::<u64>().ok());
+        let mut limits = SearchLimits::default();
+        let (mut clocks, mut increments, mut moves_to_go, mut infinite) = ([None, None], [0, 0], None, false);
+        for (i, &word) in args.iter().enumerate() {
+            match word {
+                "depth" => limits.depth = number(i).map(|depth| depth as usize),
+                "nodes" => limits.nodes = number(i),
+                "movetime" => limits.time = number(i).map(Duration::from_millis),
+                "wtime" => clocks[0] = number(i),
+                "btime" => clocks[1] = number(i),
+                "winc" => increments[0] = number(i).unwrap_or(0),
+                "binc" => increments[1] = number(i).unwrap_or(0),
+                "movestogo" => moves_to_go = number(i),
+                "infinite" => infinite = true,
+                _ => {}
+            }
+        }
+        let side = if self.position.turn == my_project::chess::Color::White {0} else {1};
+        if let (None, Some(clock)) = (limits.time, clocks[side]) {
+            limits.time = Some(allot_time(clock, increments[side], moves_to_go));
+        }
+
+        self.stop.store(false, Ordering::Relaxed);
+        let (position, output, stop) = (self.position.clone(), self.output.clone(), self.stop.clone());
+        let start = Instant::now();
+        let handle = thread::spawn(move || {
+            let result = engine.search_with(&position, limits, |result| {
+                let _ = send(&output, &info_line(result, start.elapsed()));
+            });
+            // An infinite search only answers once told to stop, even if it has run out of depth
+            while infinite && !stop.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let best = result.best_move.map_or("0000".to_string(), |mv| mv.to_string());
+            let _ = send(&output, &format!("bestmove {}", best));
+            engine
+        });
+        self.search = Some(Search { handle, infinite });
+        Ok(())
+    }
+}
+
+// Time to spend on this move out of clock milliseconds left: an even share over the moves to go, guessing
+// 30 when the GUI doesn't say, plus most of the increment, keeping a little back for lag.
+fn allot_time(clock: u64, increment: u64, moves_to_go: Option<u64>) -> Duration {
+    let share = clock / moves_to_go.unwrap_or(30).max(1) + increment * 3 / 4;
+    Duration::from_millis(share.min(clock.saturating_sub(50)).max(1))
+}
+
+fn info_line(result: &SearchResult, elapsed: Duration) -> String {
+    let score = match result.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", result.score)
+    };
+    let millis = elapsed.as_millis() as u64;
+    let mut line = format!("info depth {} score {} nodes {} time {} nps {}",
+                           result.depth, score, result.nodes, millis, result.nodes * 1000 / millis.max(1));
+    if let Some(mv) = result.best_move {
+        line.push_str(&format!(" pv {}", mv));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Collects what the session writes, from whichever thread writes it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn session(script: &str) -> Vec<String> {
+        let output = SharedBuffer::default();
+        run(script.as_bytes(), output.clone()).unwrap();
+        let bytes = output.0.lock().unwrap().clone();
+        String::from_utf8(bytes).unwrap().lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_handshake() {
+        let lines = session("uci\nsetoption name Hash value 1\nsetoption name Clear Hash\nisready\nquit\ngo depth 1\n");
+        assert!(lines[0].starts_with("id name"));
+        assert!(lines.iter().any(|line| line.starts_with("option name Hash")));
+        assert_eq!(lines[lines.len() - 2..], ["uciok", "readyok"]);
+    }
+
+    #[test]
+    fn test_go() {
+        let lines = session("position startpos moves e2e4 e7e5 g1f3\ngo depth 3\n");
+        assert!(lines.iter().any(|line| line.starts_with("info depth 3 score cp")));
+        let best = lines.last().unwrap().strip_prefix("bestmove ").unwrap();
+        let state = GameState::from_fen("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2").unwrap();
+        assert!(state.parse_uci(best).is_ok());
+        // Mate is found and reported as such, and the search ends without using all its time
+        let start = Instant::now();
+        let lines = session("position fen 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1\ngo movetime 20000\n");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(lines.iter().any(|line| line.contains("score mate 1")));
+        assert_eq!(lines.last().unwrap(), "bestmove a1a8");
+        // Clock times, and a checkmated side with nothing to play
+        let lines = session("position startpos\ngo wtime 1000 btime 1000 winc 10 binc 10\nstop\n");
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+        let lines = session("position startpos moves f2f3 e7e5 g2g4 d8h4\ngo depth 2\n");
+        assert_eq!(lines.last().unwrap(), "bestmove 0000");
+    }
+
+    #[test]
+    fn test_stop() {
+        let lines = session("go infinite\nisready\nstop\nisready\n");
+        assert!(lines.contains(&"readyok".to_string()));
+        assert_eq!(lines.iter().filter(|line| line.starts_with("bestmove ")).count(), 1);
+        // Nothing stops an infinite search but stop, or the input running out
+        let lines = session("go infinite\n");
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_bad_input() {
+        let lines = session("position fen nonsense\nposition startpos moves e2e5\nsetoption name Bogus value 3\nfoo\nposition startpos moves e2e4\ngo depth 1\n");
+        assert_eq!(lines.iter().filter(|line| line.starts_with("info string")).count(), 4);
+        // e2e4 was played, so the engine answers for Black
+        let best = lines.last().unwrap().strip_prefix("bestmove ").unwrap();
+        assert!(best.ends_with('5') || best.ends_with('6'));
+    }
+
+    #[test]
+    fn test_own_book() {
+        let path = std::env::temp_dir().join(format!("uci-book-{}.bin", std::process::id()));
+        let mut builder = my_project::chess::polyglot::BookBuilder::new(4);
+        builder.add_game(&my_project::chess::pgn::PgnGame::from_pgn("[Result \"1-0\"]\n\n1. Nc3 1-0\n").unwrap());
+        builder.build().save(&path).unwrap();
+        let script = format!("setoption name Book File value {}\nsetoption name OwnBook value true\ngo depth 4\n", path.display());
+        let lines = session(&script);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines.last().unwrap(), "bestmove b1c3");
+        let lines = session("setoption name OwnBook value true\nsetoption name Book File value /nonexistent/book.bin\n");
+        assert!(lines[0].starts_with("info string Can't read book"));
+    }
+
+    #[test]
+    fn test_variants() {
+        // Antichess: the bishop has to take
+        let lines = session("setoption name UCI_Variant value antichess\nposition startpos moves e2e3 b7b5\ngo depth 2\n");
+        assert_eq!(lines.last().unwrap(), "bestmove f1b5");
+        // Still Antichess when the GUI sends every option, UCI_Chess960 included, after picking the variant
+        let lines = session("setoption name UCI_Variant value antichess\nsetoption name UCI_Chess960 value false\nposition startpos moves e2e3 b7b5\ngo depth 2\n");
+        assert_eq!(lines.last().unwrap(), "bestmove f1b5");
+        // Chess960 castling is sent as the king taking its own rook
+        let lines = session("setoption name UCI_Chess960 value true\nposition fen 4k3/8/8/8/8/8/8/5KR1 w G - 0 1 moves f1g1\ngo depth 1\n");
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+        assert!(!lines.iter().any(|line| line.starts_with("info string")));
+        // Atomic starts from the standard setup, but a standard book's moves don't apply to it
+        let path = std::env::temp_dir().join(format!("uci-variant-book-{}.bin", std::process::id()));
+        let mut builder = my_project::chess::polyglot::BookBuilder::new(4);
+        builder.add_game(&my_project::chess::pgn::PgnGame::from_pgn("[Result \"1-0\"]\n\n1. Nh3 1-0\n").unwrap());
+        builder.build().save(&path).unwrap();
+        let book = format!("setoption name Book File value {}\nsetoption name OwnBook value true\n", path.display());
+        let lines = session(&format!("{}go depth 2\n", book));
+        assert_eq!(lines.last().unwrap(), "bestmove g1h3");
+        let lines = session(&format!("setoption name UCI_Variant value atomic\n{}position startpos\ngo depth 2\n", book));
+        std::fs::remove_file(&path).unwrap();
+        assert_ne!(lines.last().unwrap(), "bestmove g1h3");
+    }
+
+    #[test]
+    fn test_allot_time() {
+        assert_eq!(allot_time(60_000, 0, None), Duration::from_millis(2000));
+        assert_eq!(allot_time(60_000, 1000, Some(10)), Duration::from_millis(6750));
+        // Never the whole clock
+        assert_eq!(allot_time(100, 1000, Some(1)), Duration::from_millis(50));
+        assert_eq!(allot_time(0, 0, None), Duration::from_millis(1));
+    }
+}